@@ -1,5 +1,5 @@
 use allow_me::{
-    Decision, PolicyBuilder, Request, ResourceMatcher, Result, Substituter, VariableIter,
+    Decision, Field, PolicyBuilder, Request, ResourceMatcher, Result, Substituter, Token, Tokenizer,
 };
 
 fn main() -> Result<()> {
@@ -68,13 +68,14 @@ impl Substituter for RoleSubstituter {
     fn visit_resource(&self, value: &str, context: &Request<Self::Context>) -> Result<String> {
         match context.context() {
             Some(role_context) => {
-                let mut result = value.to_owned();
-                for variable in VariableIter::new(value) {
-                    result = match variable {
-                        "{{any}}" => replace(&result, variable, context.resource()),
-                        "{{role}}" => replace(&result, variable, &role_context.role),
-                        _ => result,
-                    };
+                let mut result = String::new();
+                for token in Tokenizer::new(value) {
+                    match token? {
+                        Token::Literal(literal) => result.push_str(literal),
+                        Token::Variable("{{any}}") => result.push_str(context.resource()),
+                        Token::Variable("{{role}}") => result.push_str(&role_context.role),
+                        Token::Variable(variable) => result.push_str(variable),
+                    }
                 }
                 Ok(result)
             }
@@ -82,6 +83,14 @@ impl Substituter for RoleSubstituter {
         }
     }
 
+    // declare supported variables, so that typos fail the policy build.
+    fn variables(&self, field: Field) -> Option<&[&str]> {
+        match field {
+            Field::Resources => Some(&["{{any}}", "{{role}}"]),
+            _ => Some(&[]),
+        }
+    }
+
     // skipping the rest of the implementation...
     fn visit_identity(&self, value: &str, _context: &Request<Self::Context>) -> Result<String> {
         Ok(value.to_owned())
@@ -91,7 +100,3 @@ impl Substituter for RoleSubstituter {
         Ok(value.to_owned())
     }
 }
-
-fn replace(value: &str, variable: &str, substitution: &str) -> String {
-    value.replace(variable, substitution)
-}
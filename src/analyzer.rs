@@ -0,0 +1,459 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    matcher::{MatchSemantics, NormalizedPath},
+    substituter::ANY_VAR,
+    validator::Severity,
+    Diagnostic, Field, PolicyDefinition, PolicyValidator, Statement, ValidatorError,
+};
+
+/// Finds statements that are shadowed by earlier statements.
+///
+/// Since the first matching statement wins, a statement that only matches requests
+/// already matched by earlier statements can never affect a decision. A statement
+/// that shares some requests with earlier statements is partially shadowed.
+///
+/// The analysis is conservative: variables other than `{{any}}` are only compared
+/// literally, so some shadowing through variables is not reported.
+///
+/// Both kinds of findings are reported as warnings by default.
+#[derive(Debug)]
+pub struct PolicyAnalyzer {
+    semantics: MatchSemantics,
+    unreachable: Severity,
+    partially_shadowed: Severity,
+}
+
+impl PolicyAnalyzer {
+    /// Creates an analyzer for policies evaluated by a matcher with the given semantics.
+    pub fn new(semantics: MatchSemantics) -> Self {
+        Self {
+            semantics,
+            unreachable: Severity::Warn,
+            partially_shadowed: Severity::Warn,
+        }
+    }
+
+    /// Specifies how statements that can never affect a decision are reported.
+    #[must_use]
+    pub fn with_unreachable(mut self, severity: Severity) -> Self {
+        self.unreachable = severity;
+        self
+    }
+
+    /// Specifies how partially shadowed statements are reported.
+    #[must_use]
+    pub fn with_partially_shadowed(mut self, severity: Severity) -> Self {
+        self.partially_shadowed = severity;
+        self
+    }
+
+    /// Returns all shadowed statements of the policy definition.
+    pub fn analyze(&self, definition: &PolicyDefinition) -> Vec<Shadowing> {
+        let statements = definition.statements();
+        let mut result = vec![];
+        for (index, statement) in statements.iter().enumerate() {
+            let earlier = &statements[..index];
+            let mut covered = true;
+            let mut shadowed_by = BTreeSet::new();
+
+            for (identity, operation, resource) in rules(statement) {
+                let mut rule_covered = false;
+                for (position, other) in earlier.iter().enumerate() {
+                    if self.covers(other, identity, operation, resource) {
+                        shadowed_by.insert(position);
+                        rule_covered = true;
+                        break;
+                    }
+                    if self.overlaps(other, identity, operation, resource) {
+                        shadowed_by.insert(position);
+                    }
+                }
+                covered &= rule_covered;
+            }
+
+            if !shadowed_by.is_empty() {
+                result.push(Shadowing {
+                    statement: index,
+                    shadowed_by: shadowed_by.into_iter().collect(),
+                    partial: !covered,
+                });
+            }
+        }
+        result
+    }
+
+    fn diagnostics(&self, definition: &PolicyDefinition, severity: Severity) -> Vec<Diagnostic> {
+        self.analyze(definition)
+            .into_iter()
+            .filter(|shadowing| {
+                let expected = if shadowing.partial {
+                    self.partially_shadowed
+                } else {
+                    self.unreachable
+                };
+                expected == severity
+            })
+            .map(|shadowing| {
+                let statement = &definition.statements()[shadowing.statement];
+                Diagnostic::new(statement, Field::Statement, shadowing.to_string())
+            })
+            .collect()
+    }
+
+    /// Checks if every request matching the rule is matched by the statement.
+    fn covers(
+        &self,
+        statement: &Statement,
+        identity: &str,
+        operation: &str,
+        resource: &str,
+    ) -> bool {
+        statement
+            .identities()
+            .iter()
+            .any(|policy| covers_value(policy, identity))
+            && statement
+                .operations()
+                .iter()
+                .any(|policy| covers_value(policy, operation))
+            && resources(statement).any(|policy| self.covers_resource(policy, resource))
+    }
+
+    /// Checks if some request matching the rule is matched by the statement.
+    fn overlaps(
+        &self,
+        statement: &Statement,
+        identity: &str,
+        operation: &str,
+        resource: &str,
+    ) -> bool {
+        statement
+            .identities()
+            .iter()
+            .any(|policy| overlaps_value(policy, identity))
+            && statement
+                .operations()
+                .iter()
+                .any(|policy| overlaps_value(policy, operation))
+            && resources(statement).any(|policy| self.overlaps_resource(policy, resource))
+    }
+
+    fn covers_resource(&self, policy: &str, resource: &str) -> bool {
+        if covers_value(policy, resource) {
+            return true;
+        }
+        if has_variables(policy) {
+            return false;
+        }
+
+        match self.semantics {
+            // captures are only compared literally.
+            MatchSemantics::Equality | MatchSemantics::Captures => false,
+            // a variable can only extend the literal prefix of the resource.
+            MatchSemantics::StartsWith => literal_prefix(resource).starts_with(policy),
+            MatchSemantics::PathPrefix => {
+                !has_variables(resource)
+                    && match (NormalizedPath::new(resource), NormalizedPath::new(policy)) {
+                        (Some(resource), Some(policy)) => resource.starts_with(&policy),
+                        _ => false,
+                    }
+            }
+            MatchSemantics::Glob => {
+                !has_variables(resource)
+                    && glob_reaches(policy.as_bytes(), resource.as_bytes(), false)
+            }
+        }
+    }
+
+    fn overlaps_resource(&self, policy: &str, resource: &str) -> bool {
+        if overlaps_value(policy, resource)
+            || self.covers_resource(policy, resource)
+            || self.covers_resource(resource, policy)
+        {
+            return true;
+        }
+
+        self.semantics == MatchSemantics::Glob
+            && !has_variables(policy)
+            && !has_variables(resource)
+            && glob_reaches(policy.as_bytes(), resource.as_bytes(), true)
+    }
+}
+
+impl PolicyValidator for PolicyAnalyzer {
+    type Error = ValidatorError;
+
+    fn validate(&self, definition: &PolicyDefinition) -> Result<(), Self::Error> {
+        let errors = self.diagnostics(definition, Severity::Fail);
+        if !errors.is_empty() {
+            return Err(ValidatorError::ValidationSummary(errors));
+        }
+        Ok(())
+    }
+
+    fn warnings(&self, definition: &PolicyDefinition) -> Vec<Diagnostic> {
+        self.diagnostics(definition, Severity::Warn)
+    }
+}
+
+/// A statement shadowed by earlier statements of the policy definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shadowing {
+    statement: usize,
+    shadowed_by: Vec<usize>,
+    partial: bool,
+}
+
+impl Shadowing {
+    /// The index of the shadowed statement.
+    pub fn statement(&self) -> usize {
+        self.statement
+    }
+
+    /// The indexes of the earlier statements that shadow the statement.
+    pub fn shadowed_by(&self) -> &[usize] {
+        &self.shadowed_by
+    }
+
+    /// Returns `true` if the statement still affects some decisions.
+    pub fn is_partial(&self) -> bool {
+        self.partial
+    }
+}
+
+impl std::fmt::Display for Shadowing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let statements = self
+            .shadowed_by
+            .iter()
+            .map(|index| format!("#{index}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let plural = if self.shadowed_by.len() > 1 { "s" } else { "" };
+
+        if self.partial {
+            write!(
+                f,
+                "Statement is partially shadowed by statement{plural} {statements}"
+            )
+        } else {
+            write!(
+                f,
+                "Statement can never affect a decision, it is shadowed by statement{plural} {statements}"
+            )
+        }
+    }
+}
+
+/// Returns every identity, operation and resource combination of the statement.
+fn rules(statement: &Statement) -> impl Iterator<Item = (&str, &str, &str)> {
+    statement.identities().iter().flat_map(move |identity| {
+        statement.operations().iter().flat_map(move |operation| {
+            resources(statement)
+                .map(move |resource| (identity.as_str(), operation.as_str(), resource))
+        })
+    })
+}
+
+/// Statements without resources are stored with an empty resource.
+fn resources(statement: &Statement) -> impl Iterator<Item = &str> {
+    let empty = if statement.resources().is_empty() {
+        Some("")
+    } else {
+        None
+    };
+    statement
+        .resources()
+        .iter()
+        .map(String::as_str)
+        .chain(empty)
+}
+
+fn covers_value(policy: &str, value: &str) -> bool {
+    policy == ANY_VAR || policy == value
+}
+
+fn overlaps_value(policy: &str, value: &str) -> bool {
+    policy == ANY_VAR || value == ANY_VAR || policy == value
+}
+
+fn has_variables(value: &str) -> bool {
+    value.contains("{{")
+}
+
+fn literal_prefix(value: &str) -> &str {
+    value.find("{{").map_or(value, |end| &value[..end])
+}
+
+/// Checks if every string matched by the glob `b` is matched by the glob `a`,
+/// or, if `intersect` is set, whether any string is matched by both globs.
+fn glob_reaches(a: &[u8], b: &[u8], intersect: bool) -> bool {
+    let mut reached = vec![vec![false; b.len() + 1]; a.len() + 1];
+    reached[0][0] = true;
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            if !reached[i][j] {
+                continue;
+            }
+            let (x, y) = (a.get(i), b.get(j));
+            if x == Some(&b'*') {
+                reached[i + 1][j] = true;
+                if j < b.len() {
+                    reached[i][j + 1] = true;
+                }
+            }
+            if intersect && y == Some(&b'*') {
+                reached[i][j + 1] = true;
+                if i < a.len() {
+                    reached[i + 1][j] = true;
+                }
+            }
+            if let (Some(x), Some(y)) = (x, y) {
+                if x == y && *x != b'*' {
+                    reached[i + 1][j + 1] = true;
+                }
+            }
+        }
+    }
+    reached[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::{DefaultValidator, PolicyBuilder};
+
+    fn definition(statements: &[(&str, &str, &str)]) -> PolicyDefinition {
+        let mut builder = PolicyDefinition::builder();
+        for (identity, operation, resource) in statements {
+            builder = builder.statement(|s| {
+                s.allow()
+                    .identity(*identity)
+                    .operation(*operation)
+                    .resource(*resource)
+            });
+        }
+        builder.build()
+    }
+
+    #[test_case(MatchSemantics::Equality, "resource_1", "resource_1", false; "equal resources")]
+    #[test_case(MatchSemantics::Equality, "{{any}}", "resource_1", false; "any resource")]
+    #[test_case(MatchSemantics::Equality, "resource_1", "{{any}}", true; "any resource after specific")]
+    #[test_case(MatchSemantics::StartsWith, "/home", "/home/alice", false; "starts with prefix")]
+    #[test_case(MatchSemantics::StartsWith, "/home/", "/home/{{identity}}", false; "starts with variable")]
+    #[test_case(MatchSemantics::StartsWith, "/home/alice", "/home", true; "starts with wider")]
+    #[test_case(MatchSemantics::PathPrefix, "/home", "/home/alice/", false; "path prefix")]
+    #[test_case(MatchSemantics::Glob, "/home/*", "/home/*.txt", false; "glob wider")]
+    #[test_case(MatchSemantics::Glob, "*.txt", "/home/*", true; "glob intersecting")]
+    fn shadowed_resource_test(semantics: MatchSemantics, first: &str, second: &str, partial: bool) {
+        let definition = definition(&[("actor_a", "read", first), ("actor_a", "read", second)]);
+
+        let shadowing = PolicyAnalyzer::new(semantics).analyze(&definition);
+
+        assert_eq!(1, shadowing.len());
+        assert_eq!(1, shadowing[0].statement());
+        assert_eq!(&[0], shadowing[0].shadowed_by());
+        assert_eq!(partial, shadowing[0].is_partial());
+    }
+
+    #[test_case(MatchSemantics::Equality, "resource_1", "resource_2"; "different resources")]
+    #[test_case(MatchSemantics::StartsWith, "/home/alice", "/home/bob"; "different prefixes")]
+    #[test_case(MatchSemantics::PathPrefix, "/home/al", "/home/alice"; "partial segment")]
+    #[test_case(MatchSemantics::Glob, "*.txt", "*.md"; "different extensions")]
+    #[test_case(MatchSemantics::Equality, "{{identity}}", "resource_1"; "unknown variable")]
+    fn not_shadowed_resource_test(semantics: MatchSemantics, first: &str, second: &str) {
+        let definition = definition(&[("actor_a", "read", first), ("actor_a", "read", second)]);
+
+        assert!(PolicyAnalyzer::new(semantics)
+            .analyze(&definition)
+            .is_empty());
+    }
+
+    #[test]
+    fn statement_shadowed_by_several_statements_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource("resource_1")
+            })
+            .statement(|s| {
+                s.deny()
+                    .identity("{{any}}")
+                    .operation("write")
+                    .resource("resource_1")
+            })
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_b")
+                    .operation("read")
+                    .resource("resource_1")
+            })
+            .statement(|s| {
+                s.deny()
+                    .identities(vec!["actor_a", "actor_b"])
+                    .operations(vec!["read", "write"])
+                    .resource("resource_1")
+            })
+            .build();
+
+        let shadowing = PolicyAnalyzer::new(MatchSemantics::Equality).analyze(&definition);
+
+        assert_eq!(1, shadowing.len());
+        assert_eq!(3, shadowing[0].statement());
+        assert_eq!(&[0, 1, 2], shadowing[0].shadowed_by());
+        assert!(!shadowing[0].is_partial());
+        assert_eq!(
+            "Statement can never affect a decision, it is shadowed by statements #0, #1, #2",
+            shadowing[0].to_string()
+        );
+    }
+
+    #[test]
+    fn analyzer_warnings_test() {
+        let json = include_str!("../examples/order.rs");
+        let json = &json[json.find("r#\"").unwrap() + 3..json.find("\"#").unwrap()];
+
+        let policy = PolicyBuilder::from_json(json)
+            .with_validator((
+                DefaultValidator,
+                PolicyAnalyzer::new(MatchSemantics::Equality),
+            ))
+            .build()
+            .unwrap();
+
+        assert_eq!(1, policy.warnings().len());
+        assert_eq!(1, policy.warnings()[0].statement());
+        assert_eq!(
+            "statement #1 at line 15, /statements/1: Statement is partially shadowed by statement #0",
+            policy.warnings()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn analyzer_fails_validation_test() {
+        let definition = definition(&[
+            ("actor_a", "read", "{{any}}"),
+            ("actor_a", "read", "resource_1"),
+        ]);
+
+        let result = PolicyBuilder::from_definition(definition)
+            .with_validator(
+                PolicyAnalyzer::new(MatchSemantics::Equality)
+                    .with_unreachable(Severity::Fail)
+                    .with_partially_shadowed(Severity::Ignore),
+            )
+            .build();
+
+        let error = assert_matches!(result, Err(crate::Error::Validation(e)) => e);
+        assert_eq!(
+            "statement #1, /statements/1: Statement can never affect a decision, it is shadowed by statement #0",
+            error.to_string()
+        );
+    }
+}
@@ -0,0 +1,471 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    hash::Hash,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    normalizer, Decision, Normalizer, Policy, PolicyHandle, PolicySnapshot, Request,
+    ResourceMatcher, Result, Substituter,
+};
+
+/// A [`PolicyHandle`] wrapper that caches decisions in an LRU cache with a time to live.
+///
+/// Decisions are keyed on the identity, operation and resource of the request, and a key
+/// derived from the request context. Cached decisions are invalidated when the generation
+/// of the handle changes, e.g. when a [`PolicyWatcher`](`crate::PolicyWatcher`) reloads it.
+///
+/// Requests that may match variable rules depending on anything besides the identity,
+/// operation and resource of the request, e.g. its context or time, bypass the cache,
+/// see [`Substituter::is_volatile`]. By default, requests with a context bypass the cache
+/// as well, since a custom matcher may use it. Use [`CachedPolicy::with_context_key`]
+/// to cache them, or to bypass the cache for other requests.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use allow_me::{CachedPolicy, Decision, PolicyBuilder, Request};
+///
+/// let json = r#"{
+///     "statements": [
+///         {
+///             "effect": "allow",
+///             "identities": [ "actor_a" ],
+///             "operations": [ "write" ],
+///             "resources": [ "resource_1" ]
+///         }
+///     ]
+/// }"#;
+///
+/// let policy = PolicyBuilder::from_json(json).build().unwrap();
+/// let cached = CachedPolicy::new(policy, 1000, Duration::from_secs(30));
+///
+/// let request = Request::new("actor_a", "write", "resource_1").unwrap();
+/// assert_eq!(Decision::Allowed, cached.evaluate(&request).unwrap());
+/// // the second evaluation is served from the cache.
+/// assert_eq!(Decision::Allowed, cached.evaluate(&request).unwrap());
+/// ```
+pub struct CachedPolicy<R: ResourceMatcher, S, N = normalizer::Default, K = ()> {
+    handle: Arc<PolicyHandle<R, S, N>>,
+    state: Mutex<State<K>>,
+    ttl: Duration,
+    context_key: ContextKey<R::Context, K>,
+}
+
+type ContextKey<RC, K> = Box<dyn Fn(&Request<'_, RC>) -> Option<K> + Send + Sync>;
+
+struct State<K> {
+    /// The generation of the policy that made the cached decisions.
+    generation: u64,
+    decisions: Lru<(String, String, String, K), (Decision, Instant)>,
+}
+
+impl<R, S, N, RC> CachedPolicy<R, S, N, ()>
+where
+    R: ResourceMatcher<Context = RC>,
+{
+    /// Creates a cache of at most `capacity` decisions, each valid for `ttl`.
+    pub fn new(policy: Policy<R, S, N>, capacity: usize, ttl: Duration) -> Self {
+        Self::from_handle(Arc::new(PolicyHandle::new(policy)), capacity, ttl)
+    }
+
+    /// Creates a cache of the policy of the handle. Cached decisions are invalidated
+    /// when the handle is replaced.
+    pub fn from_handle(handle: Arc<PolicyHandle<R, S, N>>, capacity: usize, ttl: Duration) -> Self {
+        Self {
+            state: Mutex::new(State {
+                generation: handle.generation(),
+                decisions: Lru::new(capacity),
+            }),
+            handle,
+            ttl,
+            context_key: Box::new(|request| request.context().is_none().then_some(())),
+        }
+    }
+}
+
+impl<R, S, N, K, RC> CachedPolicy<R, S, N, K>
+where
+    R: ResourceMatcher<Context = RC>,
+    K: Hash + Eq + Clone,
+{
+    /// Specifies the part of the cache key derived from the request.
+    /// Requests for which the function returns `None` bypass the cache.
+    ///
+    /// The cached decisions are dropped, since their keys change, so the key is
+    /// usually specified when the cache is created.
+    #[must_use]
+    pub fn with_context_key<K1>(
+        self,
+        context_key: impl Fn(&Request<'_, RC>) -> Option<K1> + Send + Sync + 'static,
+    ) -> CachedPolicy<R, S, N, K1> {
+        let state = self
+            .state
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        CachedPolicy {
+            handle: self.handle,
+            state: Mutex::new(State {
+                generation: state.generation,
+                decisions: Lru::new(state.decisions.capacity),
+            }),
+            ttl: self.ttl,
+            context_key: Box::new(context_key),
+        }
+    }
+
+    /// Returns the handle of the cached policy.
+    pub fn handle(&self) -> &Arc<PolicyHandle<R, S, N>> {
+        &self.handle
+    }
+
+    /// Returns the current policy.
+    pub fn policy(&self) -> Arc<PolicySnapshot<R, S, N>> {
+        self.handle.load()
+    }
+
+    /// Replaces the policy of the handle and invalidates all cached decisions.
+    ///
+    /// Decisions of the previous policy that are evaluated concurrently are not cached.
+    pub fn replace(&self, policy: Policy<R, S, N>) {
+        self.handle.replace(policy);
+    }
+
+    /// Removes all cached decisions.
+    pub fn clear(&self) {
+        self.lock().decisions.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<K>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<K: Hash + Eq + Clone> State<K> {
+    /// Removes the decisions of generations older than `generation`. Returns `true`
+    /// if the decisions are of the generation.
+    fn sync(&mut self, generation: u64) -> bool {
+        if generation > self.generation {
+            self.generation = generation;
+            self.decisions.clear();
+        }
+        generation == self.generation
+    }
+}
+
+impl<R, S, N, K, RC> CachedPolicy<R, S, N, K>
+where
+    R: ResourceMatcher<Context = RC>,
+    S: Substituter<Context = RC>,
+    N: Normalizer,
+    K: Hash + Eq + Clone,
+{
+    /// Returns the cached decision for the request, or evaluates it with the current policy.
+    /// Errors are not cached.
+    pub fn evaluate(&self, request: &Request<'_, RC>) -> Result<Decision> {
+        let policy = self.handle.load();
+        let key = match (self.context_key)(request) {
+            Some(context) if !policy.is_volatile(request) => (
+                request.identity().to_string(),
+                request.operation().to_string(),
+                request.resource().to_string(),
+                context,
+            ),
+            _ => return policy.evaluate(request),
+        };
+
+        {
+            let mut state = self.lock();
+            if state.sync(policy.generation()) {
+                if let Some((decision, expires)) = state.decisions.get(&key) {
+                    if Instant::now() < *expires {
+                        return Ok(*decision);
+                    }
+                }
+            }
+        }
+
+        let decision = policy.evaluate(request)?;
+
+        let mut state = self.lock();
+        // the policy was replaced during the evaluation.
+        if state.sync(policy.generation()) {
+            state
+                .decisions
+                .insert(key, (decision, Instant::now() + self.ttl));
+        }
+        Ok(decision)
+    }
+}
+
+impl<R: ResourceMatcher, S, N, K> fmt::Debug for CachedPolicy<R, S, N, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedPolicy")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A least recently used cache.
+struct Lru<K, V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<K, (V, u64)>,
+    /// Keys by the tick of their last use.
+    order: BTreeMap<u64, K>,
+}
+
+impl<K, V> Lru<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Lru<K, V> {
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let (value, used) = self.entries.get_mut(key)?;
+        self.tick += 1;
+        if let Some(key) = self.order.remove(used) {
+            self.order.insert(self.tick, key);
+        }
+        *used = self.tick;
+        Some(value)
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.get(&key) {
+            self.order.remove(used);
+        } else if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{DefaultSubstituter, PolicyBuilder};
+
+    use super::*;
+
+    /// Equality matcher that counts its calls.
+    #[derive(Debug, Default)]
+    struct CountingMatcher(Arc<AtomicUsize>);
+
+    impl ResourceMatcher for CountingMatcher {
+        type Context = ();
+
+        fn do_match(&self, _: &Request<'_, Self::Context>, input: &str, policy: &str) -> bool {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            input == policy
+        }
+    }
+
+    fn build(
+        effect: &str,
+        calls: &Arc<AtomicUsize>,
+    ) -> Policy<CountingMatcher, DefaultSubstituter> {
+        let json = format!(
+            r#"{{
+                "statements": [
+                    {{
+                        "effect": "{effect}",
+                        "identities": [ "actor_a" ],
+                        "operations": [ "write" ],
+                        "resources": [ "resource_1" ]
+                    }}
+                ]
+            }}"#
+        );
+        PolicyBuilder::from_json(&json)
+            .with_matcher(CountingMatcher(Arc::clone(calls)))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn cache_hit_test() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cached = CachedPolicy::new(build("allow", &calls), 10, Duration::from_secs(30));
+        let request = Request::new("actor_a", "write", "resource_1").unwrap();
+
+        assert_eq!(Decision::Allowed, cached.evaluate(&request).unwrap());
+        assert_eq!(Decision::Allowed, cached.evaluate(&request).unwrap());
+
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn expired_decision_test() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cached = CachedPolicy::new(build("allow", &calls), 10, Duration::ZERO);
+        let request = Request::new("actor_a", "write", "resource_1").unwrap();
+
+        cached.evaluate(&request).unwrap();
+        cached.evaluate(&request).unwrap();
+
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn context_bypasses_cache_test() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cached = CachedPolicy::new(build("allow", &calls), 10, Duration::from_secs(30));
+        let request = Request::with_context("actor_a", "write", "resource_1", ()).unwrap();
+
+        cached.evaluate(&request).unwrap();
+        cached.evaluate(&request).unwrap();
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+
+        let cached = cached.with_context_key(|_| Some("tenant"));
+        cached.evaluate(&request).unwrap();
+        cached.evaluate(&request).unwrap();
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+    }
+
+    /// Renders `{{tenant}}` from the request context.
+    #[derive(Debug)]
+    struct TenantSubstituter;
+
+    impl Substituter for TenantSubstituter {
+        type Context = &'static str;
+
+        fn visit_identity(&self, value: &str, _: &Request<'_, Self::Context>) -> Result<String> {
+            Ok(value.to_string())
+        }
+
+        fn visit_operation(&self, value: &str, _: &Request<'_, Self::Context>) -> Result<String> {
+            Ok(value.to_string())
+        }
+
+        fn visit_resource(
+            &self,
+            value: &str,
+            request: &Request<'_, Self::Context>,
+        ) -> Result<String> {
+            Ok(value.replace("{{tenant}}", request.context().copied().unwrap_or_default()))
+        }
+    }
+
+    #[derive(Debug)]
+    struct TenantMatcher;
+
+    impl ResourceMatcher for TenantMatcher {
+        type Context = &'static str;
+
+        fn do_match(&self, _: &Request<'_, Self::Context>, input: &str, policy: &str) -> bool {
+            input == policy
+        }
+    }
+
+    #[test]
+    fn volatile_rule_bypasses_cache_test() {
+        let json = r#"{
+            "statements": [
+                {
+                    "effect": "allow",
+                    "identities": [ "actor_a" ],
+                    "operations": [ "write" ],
+                    "resources": [ "{{tenant}}/data" ]
+                },
+                {
+                    "effect": "allow",
+                    "identities": [ "actor_a" ],
+                    "operations": [ "read" ],
+                    "resources": [ "shared/data" ]
+                }
+            ]
+        }"#;
+        let policy = PolicyBuilder::from_json(json)
+            .with_matcher(TenantMatcher)
+            .with_substituter(TenantSubstituter)
+            .build()
+            .unwrap();
+        let cached =
+            CachedPolicy::new(policy, 10, Duration::from_secs(30)).with_context_key(|_| Some(()));
+
+        let request = Request::with_context("actor_a", "write", "tenant_a/data", "tenant_a");
+        assert_eq!(
+            Decision::Allowed,
+            cached.evaluate(&request.unwrap()).unwrap()
+        );
+        // the decision of the first tenant is not served to the second one.
+        let request = Request::with_context("actor_a", "write", "tenant_a/data", "tenant_b");
+        assert_eq!(
+            Decision::Denied,
+            cached.evaluate(&request.unwrap()).unwrap()
+        );
+        assert_eq!(0, cached.lock().decisions.entries.len());
+
+        // requests that only match static rules are cached.
+        let request = Request::with_context("actor_a", "read", "shared/data", "tenant_a");
+        assert_eq!(
+            Decision::Allowed,
+            cached.evaluate(&request.unwrap()).unwrap()
+        );
+        assert_eq!(1, cached.lock().decisions.entries.len());
+    }
+
+    #[test]
+    fn replace_invalidates_cache_test() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cached = CachedPolicy::new(build("allow", &calls), 10, Duration::from_secs(30));
+        let request = Request::new("actor_a", "write", "resource_1").unwrap();
+        assert_eq!(Decision::Allowed, cached.evaluate(&request).unwrap());
+
+        cached.replace(build("deny", &calls));
+
+        assert_eq!(Decision::Denied, cached.evaluate(&request).unwrap());
+    }
+
+    #[test]
+    fn handle_replace_invalidates_cache_test() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handle = Arc::new(PolicyHandle::new(build("allow", &calls)));
+        let cached = CachedPolicy::from_handle(Arc::clone(&handle), 10, Duration::from_secs(30));
+        let request = Request::new("actor_a", "write", "resource_1").unwrap();
+        assert_eq!(Decision::Allowed, cached.evaluate(&request).unwrap());
+
+        handle.replace(build("deny", &calls));
+
+        assert_eq!(Decision::Denied, cached.evaluate(&request).unwrap());
+        assert_eq!(Decision::Denied, cached.evaluate(&request).unwrap());
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+        assert_eq!(1, cached.policy().generation());
+    }
+
+    #[test]
+    fn lru_eviction_test() {
+        let mut lru = Lru::new(2);
+        lru.insert("a", 1);
+        lru.insert("b", 2);
+        assert_eq!(Some(&1), lru.get(&"a"));
+
+        lru.insert("c", 3);
+
+        assert_eq!(None, lru.get(&"b"));
+        assert_eq!(Some(&1), lru.get(&"a"));
+        assert_eq!(Some(&3), lru.get(&"c"));
+        assert_eq!(2, lru.entries.len());
+    }
+}
@@ -0,0 +1,321 @@
+use std::borrow::Cow;
+
+use super::{Decision, Policy, Renders, Request, Rules, Values};
+use crate::{Normalizer, ResourceMatcher, Result, Substituter};
+
+/// The number of requests that share lookups and rendered templates.
+const CHUNK: usize = 256;
+
+impl<R, S, N, RC> Policy<R, S, N>
+where
+    R: ResourceMatcher<Context = RC>,
+    S: Substituter<Context = RC>,
+    N: Normalizer,
+{
+    /// Evaluates requests of the identity and operation for each of the resources,
+    /// e.g. to filter a listing. Returns the decisions in the order of the resources.
+    ///
+    /// The rules of the identity and operation are looked up once, and templates that
+    /// do not [read the resource](`Substituter::reads_resource`) are rendered once.
+    ///
+    /// ```rust
+    /// use allow_me::{Decision, PolicyBuilder};
+    ///
+    /// let json = r#"{
+    ///     "statements": [
+    ///         {
+    ///             "effect": "allow",
+    ///             "identities": [ "actor_a" ],
+    ///             "operations": [ "read" ],
+    ///             "resources": [ "files/{{identity}}" ]
+    ///         }
+    ///     ]
+    /// }"#;
+    ///
+    /// let policy = PolicyBuilder::from_json(json).build().unwrap();
+    /// let decisions = policy
+    ///     .evaluate_many("actor_a", "read", ["files/actor_a", "files/actor_b"])
+    ///     .unwrap();
+    /// assert_eq!(vec![Decision::Allowed, Decision::Denied], decisions);
+    /// ```
+    pub fn evaluate_many<I>(
+        &self,
+        identity: &str,
+        operation: &str,
+        resources: I,
+    ) -> Result<Vec<Decision>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let resources = resources.into_iter().collect::<Vec<_>>();
+        let requests = resources
+            .iter()
+            .map(|resource| Request::new(identity, operation, resource.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+        self.evaluate_batch(&requests)
+    }
+
+    /// Evaluates the requests and returns the decisions in the order of the requests.
+    ///
+    /// Requests with the same identity and operation share the lookups of their rules,
+    /// and those without a context also share rendered templates, see
+    /// [`Policy::evaluate_many`].
+    pub fn evaluate_batch(&self, requests: &[Request<'_, RC>]) -> Result<Vec<Decision>> {
+        let mut decisions = vec![self.default_decision; requests.len()];
+        for chunk in chunks(requests) {
+            self.evaluate_chunk(requests, &chunk, |i, decision| decisions[i] = decision)?;
+        }
+        Ok(decisions)
+    }
+
+    /// Evaluates the requests in sorted order. `chunk` is a non-empty list of
+    /// indexes of requests with the same identity and operation.
+    fn evaluate_chunk(
+        &self,
+        requests: &[Request<'_, RC>],
+        chunk: &[usize],
+        mut decide: impl FnMut(usize, Decision),
+    ) -> Result<()> {
+        let first = &requests[chunk[0]];
+        let identity = self.normalizer.normalize_identity(&first.identity);
+        let operation = self.normalizer.normalize_operation(&first.operation);
+        let static_resources = self.static_rules.get(&identity, &operation);
+        let candidates = self
+            .variable_rules
+            .candidates(&identity, &operation)
+            .collect::<Vec<_>>();
+        let mut renders = Renders::default();
+
+        for i in chunk {
+            let request = &requests[*i];
+            let values = Values {
+                identity: Cow::Borrowed(&identity),
+                operation: Cow::Borrowed(&operation),
+                resource: self.normalizer.normalize_resource(&request.resource),
+            };
+            let rules = Rules {
+                static_resources,
+                candidates: candidates.iter().copied(),
+            };
+            // a custom matcher or substituter may render templates from the context.
+            let renders = match request.context {
+                None => Some(&mut renders),
+                Some(_) => None,
+            };
+            decide(*i, self.eval_rules(request, &values, rules, renders)?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<R, S, N, RC> Policy<R, S, N>
+where
+    R: ResourceMatcher<Context = RC> + Send + Sync,
+    S: Substituter<Context = RC> + Send + Sync,
+    N: Normalizer + Send + Sync,
+    RC: Sync,
+{
+    /// Evaluates requests of the identity and operation for each of the resources in
+    /// parallel, see [`Policy::evaluate_many`].
+    pub fn par_evaluate_many<I>(
+        &self,
+        identity: &str,
+        operation: &str,
+        resources: I,
+    ) -> Result<Vec<Decision>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let resources = resources.into_iter().collect::<Vec<_>>();
+        let requests = resources
+            .iter()
+            .map(|resource| Request::new(identity, operation, resource.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+        self.par_evaluate_batch(&requests)
+    }
+
+    /// Evaluates the requests in parallel, see [`Policy::evaluate_batch`].
+    pub fn par_evaluate_batch(&self, requests: &[Request<'_, RC>]) -> Result<Vec<Decision>> {
+        use rayon::prelude::*;
+
+        let evaluated = chunks(requests)
+            .par_iter()
+            .map(|chunk| {
+                let mut decisions = Vec::with_capacity(chunk.len());
+                self.evaluate_chunk(requests, chunk, |i, decision| decisions.push((i, decision)))?;
+                Ok(decisions)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut decisions = vec![self.default_decision; requests.len()];
+        for (i, decision) in evaluated.into_iter().flatten() {
+            decisions[i] = decision;
+        }
+        Ok(decisions)
+    }
+}
+
+/// Groups indexes of the requests by identity and operation, in chunks of at most [`CHUNK`].
+fn chunks<RC>(requests: &[Request<'_, RC>]) -> Vec<Vec<usize>> {
+    let key = |i: &usize| (requests[*i].identity(), requests[*i].operation());
+    let mut sorted = (0..requests.len()).collect::<Vec<_>>();
+    sorted.sort_by_key(key);
+    sorted
+        .chunk_by(|a, b| key(a) == key(b))
+        .flat_map(|group| group.chunks(CHUNK))
+        .map(<[usize]>::to_vec)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use test_case::test_case;
+
+    use crate::{matcher, DefaultSubstituter, Error, Field, PolicyBuilder, Template};
+
+    use super::*;
+
+    /// Delegates to [`DefaultSubstituter`] and counts rendered resources.
+    #[derive(Default)]
+    struct CountingSubstituter(DefaultSubstituter, AtomicUsize);
+
+    impl Substituter for CountingSubstituter {
+        type Context = ();
+
+        fn visit_identity(&self, value: &str, context: &Request<'_, ()>) -> Result<String> {
+            self.0.visit_identity(value, context)
+        }
+
+        fn visit_operation(&self, value: &str, context: &Request<'_, ()>) -> Result<String> {
+            self.0.visit_operation(value, context)
+        }
+
+        fn visit_resource(&self, value: &str, context: &Request<'_, ()>) -> Result<String> {
+            self.0.visit_resource(value, context)
+        }
+
+        fn render_resource(
+            &self,
+            template: &Template,
+            context: &Request<'_, ()>,
+            buffer: &mut String,
+        ) -> std::result::Result<(), Error> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.render_resource(template, context, buffer)
+        }
+
+        fn reads_resource(&self, field: Field, template: &Template) -> bool {
+            self.0.reads_resource(field, template)
+        }
+    }
+
+    const POLICY: &str = r#"{
+        "statements": [
+            {
+                "effect": "deny",
+                "identities": [ "actor_a" ],
+                "operations": [ "read" ],
+                "resources": [ "files/actor_a/secrets" ]
+            },
+            {
+                "effect": "allow",
+                "identities": [ "{{any}}" ],
+                "operations": [ "read" ],
+                "resources": [ "files/{{identity}}/" ]
+            },
+            {
+                "effect": "allow",
+                "identities": [ "{{any}}" ],
+                "operations": [ "write" ],
+                "resources": [ "{{any}}" ]
+            },
+            {
+                "effect": "allow",
+                "identities": [ "actor_b" ],
+                "operations": [ "read" ],
+                "resources": [ "files/shared" ]
+            }
+        ]
+    }"#;
+
+    fn build() -> Policy<matcher::StartsWith, CountingSubstituter> {
+        PolicyBuilder::from_json(POLICY)
+            .with_matcher(matcher::StartsWith)
+            .with_substituter(CountingSubstituter::default())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn evaluate_many_renders_once_test() {
+        let policy = build();
+        let resources = (0..1000)
+            .map(|i| format!("files/actor_a/{i}"))
+            .chain(Some("files/actor_a/secrets".to_string()))
+            .chain(Some("files/actor_b/1".to_string()))
+            .collect::<Vec<_>>();
+
+        let decisions = policy.evaluate_many("actor_a", "read", &resources).unwrap();
+
+        assert_eq!(1002, decisions.len());
+        assert!(decisions[..1000].iter().all(|d| *d == Decision::Allowed));
+        assert_eq!(Decision::Denied, decisions[1000]);
+        assert_eq!(Decision::Denied, decisions[1001]);
+        // once per chunk of requests.
+        assert_eq!(4, policy.substituter.1.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn resource_variables_are_rendered_per_request_test() {
+        let policy = build();
+
+        let decisions = policy
+            .evaluate_many("actor_a", "write", ["a", "b", "c"])
+            .unwrap();
+
+        assert_eq!(vec![Decision::Allowed; 3], decisions);
+        assert_eq!(3, policy.substituter.1.load(Ordering::SeqCst));
+    }
+
+    #[test_case(&[]; "empty")]
+    #[test_case(&[("actor_a", "read", "files/actor_a/1")]; "single")]
+    #[test_case(&[
+        ("actor_b", "read", "files/shared"),
+        ("actor_a", "read", "files/actor_a/secrets"),
+        ("actor_b", "read", "files/actor_a/1"),
+        ("actor_a", "write", "x"),
+        ("actor_a", "read", "files/actor_a/1"),
+        ("actor_b", "read", "files/actor_b/1"),
+        ("actor_c", "delete", "x"),
+    ]; "mixed")]
+    fn evaluate_batch_test(requests: &[(&str, &str, &str)]) {
+        let policy = build();
+        let requests = requests
+            .iter()
+            .map(|(identity, operation, resource)| {
+                Request::new(*identity, *operation, *resource).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let expected = requests
+            .iter()
+            .map(|request| policy.evaluate(request).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(expected, policy.evaluate_batch(&requests).unwrap());
+        #[cfg(feature = "rayon")]
+        assert_eq!(expected, policy.par_evaluate_batch(&requests).unwrap());
+    }
+
+    #[test]
+    fn evaluate_many_bad_request_test() {
+        let policy = build();
+
+        assert!(policy.evaluate_many("", "read", ["a"]).is_err());
+    }
+}
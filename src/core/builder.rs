@@ -1,13 +1,20 @@
-use std::error::Error as StdError;
-
-use lazy_static::lazy_static;
-use regex::Regex;
-use serde::Deserialize;
+use std::{
+    borrow::Cow, collections::BTreeSet, error::Error as StdError, fmt, path::PathBuf, sync::Arc,
+};
 
 use crate::{
-    core::{Identities, Operations, Resources},
-    matcher, Decision, DefaultSubstituter, DefaultValidator, Error, Policy, PolicyValidator,
-    ResourceMatcher, Result, Substituter,
+    core::{
+        static_rules::{PrefixIndex, StaticRules, StaticRulesBuilder},
+        versions, Identities, Operations, PolicyDefinition, Resources, Statement, VariableRules,
+    },
+    filter::{Filter, Filters},
+    matcher::{self, MatchSemantics},
+    normalizer,
+    substituter::is_supported,
+    tokenizer,
+    validator::{self, Severity},
+    Decision, DefaultSubstituter, DefaultValidator, Diagnostic, Error, Expression, Field, Lint,
+    Normalizer, Policy, PolicyValidator, ResourceMatcher, Result, Substituter, ValidatorError,
 };
 
 /// A policy builder, responsible for parsing policy definition
@@ -15,12 +22,15 @@ use crate::{
 ///
 /// It handles policy definition versioning and allows fine-grained
 /// configuration of [`Policy`] components.
-pub struct PolicyBuilder<V, M, S> {
+pub struct PolicyBuilder<V, M, S, N = normalizer::Default> {
     validator: V,
     matcher: M,
     substituter: S,
+    normalizer: N,
     source: Source,
     default_decision: Decision,
+    unknown_variables: Severity,
+    filters: Filters,
 }
 
 impl PolicyBuilder<DefaultValidator, matcher::Default, DefaultSubstituter> {
@@ -32,13 +42,31 @@ impl PolicyBuilder<DefaultValidator, matcher::Default, DefaultSubstituter> {
     pub fn from_json(
         json: impl Into<String>,
     ) -> PolicyBuilder<DefaultValidator, matcher::Default, DefaultSubstituter> {
-        PolicyBuilder {
-            source: Source::Json(json.into()),
-            validator: DefaultValidator,
-            matcher: matcher::Default,
-            substituter: DefaultSubstituter,
-            default_decision: Decision::Denied,
-        }
+        Self::from_source(Source::Json(json.into()))
+    }
+
+    /// Constructs a [`PolicyBuilder`] from provided yaml policy definition, with
+    /// default configuration.
+    ///
+    /// Call to this method does not parse or validate the yaml, all heavy work
+    /// is done in `build` method.
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(
+        yaml: impl Into<String>,
+    ) -> PolicyBuilder<DefaultValidator, matcher::Default, DefaultSubstituter> {
+        Self::from_source(Source::Yaml(yaml.into()))
+    }
+
+    /// Constructs a [`PolicyBuilder`] from provided toml policy definition, with
+    /// default configuration.
+    ///
+    /// Call to this method does not parse or validate the toml, all heavy work
+    /// is done in `build` method.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(
+        toml: impl Into<String>,
+    ) -> PolicyBuilder<DefaultValidator, matcher::Default, DefaultSubstituter> {
+        Self::from_source(Source::Toml(toml.into()))
     }
 
     /// Constructs a [`PolicyBuilder`] from provided policy definition struct, with
@@ -48,270 +76,550 @@ impl PolicyBuilder<DefaultValidator, matcher::Default, DefaultSubstituter> {
     /// is done in `build` method.
     pub fn from_definition(
         definition: PolicyDefinition,
+    ) -> PolicyBuilder<DefaultValidator, matcher::Default, DefaultSubstituter> {
+        Self::from_source(Source::Definition(definition))
+    }
+
+    /// Constructs a [`PolicyBuilder`] from the policy files of a directory, with
+    /// default configuration. See [`PolicyDefinition::from_dir`].
+    ///
+    /// Call to this method does not read the files, all heavy work
+    /// is done in `build` method.
+    pub fn from_dir(
+        path: impl Into<PathBuf>,
+    ) -> PolicyBuilder<DefaultValidator, matcher::Default, DefaultSubstituter> {
+        Self::from_source(Source::Dir(path.into()))
+    }
+
+    fn from_source(
+        source: Source,
     ) -> PolicyBuilder<DefaultValidator, matcher::Default, DefaultSubstituter> {
         PolicyBuilder {
-            source: Source::Definition(definition),
+            source,
             validator: DefaultValidator,
             matcher: matcher::Default,
             substituter: DefaultSubstituter,
+            normalizer: normalizer::Default,
             default_decision: Decision::Denied,
+            unknown_variables: Severity::Warn,
+            filters: Filters::default(),
         }
     }
 }
 
-impl<V, M, S, E> PolicyBuilder<V, M, S>
+impl<V, M, N> PolicyBuilder<V, M, DefaultSubstituter, N> {
+    /// Adds a custom variable filter, or replaces a built-in one with the same name.
+    /// The filter is applied to the variable rules of the policy by the [`DefaultSubstituter`].
+    ///
+    /// ```rust
+    /// use allow_me::{filter::Value, PolicyBuilder};
+    ///
+    /// let builder = PolicyBuilder::from_json(r#"{ "statements": [ ] }"#).with_filter(
+    ///     "reverse",
+    ///     |value, _: Option<&str>| match value {
+    ///         Value::Str(s) => Ok(Value::Str(s.chars().rev().collect())),
+    ///         value => Ok(value),
+    ///     },
+    /// );
+    /// ```
+    #[must_use]
+    pub fn with_filter(
+        mut self,
+        name: impl Into<String>,
+        filter: impl Filter + Send + Sync + 'static,
+    ) -> Self {
+        self.filters.insert(name.into(), filter);
+        self
+    }
+}
+
+impl<V, M, S, N, E> PolicyBuilder<V, M, S, N>
 where
     V: PolicyValidator<Error = E>,
     M: ResourceMatcher,
     S: Substituter,
+    N: Normalizer,
     E: StdError + Sync + Into<Box<dyn StdError>> + 'static,
 {
     /// Specifies the [`PolicyValidator`] to validate the policy definition.
-    pub fn with_validator<V1>(self, validator: V1) -> PolicyBuilder<V1, M, S> {
+    pub fn with_validator<V1>(self, validator: V1) -> PolicyBuilder<V1, M, S, N> {
         PolicyBuilder {
             source: self.source,
             validator,
             matcher: self.matcher,
             substituter: self.substituter,
+            normalizer: self.normalizer,
             default_decision: self.default_decision,
+            unknown_variables: self.unknown_variables,
+            filters: self.filters,
         }
     }
 
     /// Specifies the [`ResourceMatcher`] to use with Policy.
-    pub fn with_matcher<M1>(self, matcher: M1) -> PolicyBuilder<V, M1, S> {
+    pub fn with_matcher<M1>(self, matcher: M1) -> PolicyBuilder<V, M1, S, N> {
         PolicyBuilder {
             source: self.source,
             validator: self.validator,
             matcher,
             substituter: self.substituter,
+            normalizer: self.normalizer,
             default_decision: self.default_decision,
+            unknown_variables: self.unknown_variables,
+            filters: self.filters,
         }
     }
 
     /// Specifies the [`Substituter`] to use with Policy.
-    pub fn with_substituter<S1>(self, substituter: S1) -> PolicyBuilder<V, M, S1> {
+    pub fn with_substituter<S1>(self, substituter: S1) -> PolicyBuilder<V, M, S1, N> {
         PolicyBuilder {
             source: self.source,
             validator: self.validator,
             matcher: self.matcher,
             substituter,
+            normalizer: self.normalizer,
             default_decision: self.default_decision,
+            unknown_variables: self.unknown_variables,
+            filters: self.filters,
+        }
+    }
+
+    /// Specifies the [`Normalizer`] to use with Policy.
+    ///
+    /// The normalizer is applied to policy definition values without variables when
+    /// the policy is built, before it is validated, and to request values and rendered
+    /// variable rules when the policy is evaluated.
+    pub fn with_normalizer<N1>(self, normalizer: N1) -> PolicyBuilder<V, M, S, N1> {
+        PolicyBuilder {
+            source: self.source,
+            validator: self.validator,
+            matcher: self.matcher,
+            substituter: self.substituter,
+            normalizer,
+            default_decision: self.default_decision,
+            unknown_variables: self.unknown_variables,
+            filters: self.filters,
         }
     }
 
     /// Specifies the default decision that [`Policy`] will return if
     /// no rules match the request.
+    #[must_use]
     pub fn with_default_decision(mut self, decision: Decision) -> Self {
         self.default_decision = decision;
         self
     }
 
+    /// Specifies how variables that the [`Substituter`] does not support are reported.
+    ///
+    /// By default, such variables are reported in [`Policy::warnings`]. Use
+    /// [`Severity::Fail`] to fail the build instead. See [`Substituter::variables`].
+    #[must_use]
+    pub fn with_unknown_variables(mut self, severity: Severity) -> Self {
+        self.unknown_variables = severity;
+        self
+    }
+
     /// Builds a [`Policy`] consuming the builder.
     ///
     /// This method does all the heavy lifting of deserializing json, validating and
     /// constructing the policy rules tree.
     ///
+    /// The validator is dropped once the policy is built, so updates of the policy
+    /// need [`PolicyUpdate::with_validator`](`crate::PolicyUpdate::with_validator`).
+    /// Use [`PolicyBuilder::build_updatable`] to keep it.
+    ///
     /// # Errors
-    /// Returns  [`PolicyValidator::Error`] if any.
-    pub fn build(self) -> Result<Policy<M, S>> {
+    /// Returns [`Error::UnsupportedSchemaVersion`] if the definition schema version is newer
+    /// than [`PolicyDefinition::LATEST_SCHEMA_VERSION`], [`Error::Validation`] with
+    /// [`PolicyValidator::Error`] or unknown variables if any, and [`Error::PolicyTooLarge`]
+    /// if the rules exceed their size limits, e.g. 4 GiB of strings.
+    pub fn build(self) -> Result<Policy<M, S, N>> {
+        self.build_policy().map(|(policy, _)| policy)
+    }
+
+    /// Builds a [`Policy`] consuming the builder, like [`PolicyBuilder::build`], and keeps
+    /// the validator in the policy to validate its updates, see [`Policy::update`].
+    ///
+    /// # Errors
+    /// Returns the same errors as [`PolicyBuilder::build`].
+    pub fn build_updatable(self) -> Result<Policy<M, S, N>>
+    where
+        V: Send + Sync + 'static,
+    {
+        let (mut policy, validator) = self.build_policy()?;
+        policy.validator = Some(SharedValidator::new(validator));
+        Ok(policy)
+    }
+
+    /// Builds the policy without a validator to keep. Returns the validator.
+    fn build_policy(self) -> Result<(Policy<M, S, N>, V)> {
         let PolicyBuilder {
             validator,
             matcher,
             substituter,
+            normalizer,
             source,
             default_decision,
+            unknown_variables,
+            filters,
         } = self;
 
-        let mut definition: PolicyDefinition = match source {
+        let definition: PolicyDefinition = match source {
             Source::Json(json) => PolicyDefinition::from_json(&json)?,
-            Source::Definition(definition) => definition,
+            #[cfg(feature = "yaml")]
+            Source::Yaml(yaml) => PolicyDefinition::from_yaml(&yaml)?,
+            #[cfg(feature = "toml")]
+            Source::Toml(toml) => PolicyDefinition::from_toml(&toml)?,
+            Source::Definition(definition) => {
+                versions::check(definition.schema_version)?;
+                definition.ordered()
+            }
+            Source::Dir(path) => PolicyDefinition::from_dir(path)?,
         };
 
-        for (order, mut statement) in definition.statements.iter_mut().enumerate() {
-            statement.order = order;
+        let mut definition = definition;
+        for statement in &mut definition.statements {
+            normalize_statement(statement, &normalizer);
         }
+        let warnings = check(
+            &definition,
+            &|definition: &PolicyDefinition| validate(&validator, definition),
+            &BTreeSet::new(),
+            &substituter,
+            &filters,
+            unknown_variables,
+        )?;
+
+        let PolicyDefinition {
+            schema_version: _,
+            id,
+            version,
+            metadata,
+            statements,
+        } = definition;
+
+        let statements = statements
+            .into_iter()
+            .map(|statement| Some(Arc::new(statement)))
+            .collect::<Vec<_>>();
+
+        let filters = Arc::new(filters);
+        let (static_rules, variable_rules) = compile(
+            statements.iter().flatten().map(AsRef::as_ref),
+            StaticRulesBuilder::default(),
+            &matcher,
+            &substituter,
+            &normalizer,
+            &filters,
+        )?;
+
+        Ok((
+            Policy {
+                id,
+                version,
+                metadata,
+                statements,
+                warnings,
+                default_decision,
+                unknown_variables,
+                validator: None,
+                resource_matcher: Arc::new(matcher),
+                substituter: Arc::new(substituter),
+                normalizer: Arc::new(normalizer),
+                filters,
+                static_rules,
+                variable_rules,
+            },
+            validator,
+        ))
+    }
+}
 
-        validator
-            .validate(&definition)
-            .map_err(|e| Error::Validation(e.into()))?;
+/// Validates a policy definition. Returns the warnings.
+pub(super) type Validate<'a> = dyn Fn(&PolicyDefinition) -> Result<Vec<Diagnostic>> + 'a;
 
-        let mut static_rules = Identities::new();
-        let mut variable_rules = Identities::new();
+/// The [`PolicyValidator`] of a policy, kept to validate its updates.
+#[derive(Clone)]
+pub(super) struct SharedValidator(Arc<SharedValidate>);
 
-        for statement in definition.statements {
-            process_statement(&statement, &mut static_rules, &mut variable_rules);
-        }
+type SharedValidate = dyn Fn(&PolicyDefinition) -> Result<Vec<Diagnostic>> + Send + Sync;
 
-        Ok(Policy {
-            default_decision,
-            resource_matcher: matcher,
-            substituter,
-            static_rules: static_rules.0,
-            variable_rules: variable_rules.0,
-        })
+impl SharedValidator {
+    pub fn new<V, E>(validator: V) -> Self
+    where
+        V: PolicyValidator<Error = E> + Send + Sync + 'static,
+        E: StdError + Sync + Into<Box<dyn StdError>> + 'static,
+    {
+        Self(Arc::new(move |definition| validate(&validator, definition)))
     }
-}
-
-fn process_statement(
-    statement: &Statement,
-    static_rules: &mut Identities,
-    variable_rules: &mut Identities,
-) {
-    let (static_ids, variable_ids) = process_identities(statement);
 
-    static_rules.merge(static_ids);
-    variable_rules.merge(variable_ids);
+    pub fn as_validate(&self) -> &Validate<'static> {
+        &*self.0
+    }
 }
 
-fn process_identities(statement: &Statement) -> (Identities, Identities) {
-    let mut static_ids = Identities::new();
-    let mut variable_ids = Identities::new();
-    for identity in &statement.identities {
-        let (static_ops, variable_ops) = process_operations(&statement);
-
-        if is_variable_rule(identity) {
-            // if current identity has substitutions,
-            // then the whole operation subtree need
-            // to be cloned into substitutions tree.
-            let mut all = static_ops.clone();
-            all.merge(variable_ops);
-            variable_ids.insert(identity, all);
-        } else {
-            // else, divide operations and operation substitutions
-            // between identities and identity substitutions.
-            static_ids.insert(identity, static_ops);
-            variable_ids.insert(identity, variable_ops);
-        }
+impl fmt::Debug for SharedValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedValidator").finish_non_exhaustive()
     }
-
-    (static_ids, variable_ids)
 }
 
-fn process_operations(statement: &Statement) -> (Operations, Operations) {
-    let mut static_ops = Operations::new();
-    let mut variable_ops = Operations::new();
-    for operation in &statement.operations {
-        let (static_res, variable_res) = process_resources(&statement);
-
-        if is_variable_rule(operation) {
-            // if current operation has variables,
-            // then the whole resource subtree need
-            // to be cloned into variables tree.
-            let mut all = static_res.clone();
-            all.merge(variable_res);
-            variable_ops.insert(operation, all);
-        } else {
-            // else, divide static resources and variable resources
-            // between static operations and variable operation.
-            static_ops.insert(operation, static_res);
-            variable_ops.insert(operation, variable_res);
+/// Validates the definition with the validator. Returns the warnings.
+pub(super) fn validate<V, E>(
+    validator: &V,
+    definition: &PolicyDefinition,
+) -> Result<Vec<Diagnostic>>
+where
+    V: PolicyValidator<Error = E>,
+    E: StdError + Sync + Into<Box<dyn StdError>> + 'static,
+{
+    validator.validate(definition).map_err(|e| {
+        // the error of a custom validator may not be `Send`, so only its message is kept.
+        match e.into().downcast::<ValidatorError>() {
+            Ok(e) => Error::Validation(e),
+            Err(e) => Error::Validation(ValidatorError::Custom(e.to_string()).into()),
         }
-    }
-
-    (static_ops, variable_ops)
+    })?;
+    Ok(validator.warnings(definition))
 }
 
-fn process_resources(statement: &Statement) -> (Resources, Resources) {
-    let mut static_res = Resources::new();
-    let mut variable_res = Resources::new();
-    if statement.resources.is_empty() {
-        static_res.insert("", statement.into());
+/// Validates the definition. Returns the problems that do not fail the build.
+pub(super) fn check<S: Substituter>(
+    definition: &PolicyDefinition,
+    validate: &Validate<'_>,
+    changed: &BTreeSet<usize>,
+    substituter: &S,
+    filters: &Filters,
+    unknown_variables: Severity,
+) -> Result<Vec<Diagnostic>> {
+    let mut warnings = validate(definition)?;
+
+    let sids = validator::visit_sids(definition, changed);
+    if !sids.is_empty() {
+        return Err(Error::Validation(
+            ValidatorError::ValidationSummary(sids).into(),
+        ));
     }
 
-    for resource in &statement.resources {
-        // split resources into two buckets - static or variable rules:
-        let map = if is_variable_rule(resource) {
-            &mut variable_res
-        } else {
-            &mut static_res
-        };
-
-        map.insert(resource, statement.into());
+    let malformed = visit_malformed_variables(definition);
+    if !malformed.is_empty() {
+        return Err(Error::Validation(
+            ValidatorError::ValidationSummary(malformed).into(),
+        ));
     }
 
-    (static_res, variable_res)
+    let variables = match unknown_variables {
+        Severity::Ignore => vec![],
+        _ => visit_variables(definition, substituter, filters),
+    };
+    if unknown_variables == Severity::Fail && !variables.is_empty() {
+        return Err(Error::Validation(
+            ValidatorError::ValidationSummary(variables).into(),
+        ));
+    }
+    warnings.extend(variables);
+    Ok(warnings)
 }
 
-fn is_variable_rule(value: &str) -> bool {
-    lazy_static! {
-        static ref VAR_PATTERN: Regex =
-            Regex::new(r#"\{\{[^\{\}]+\}\}"#).expect("failed to create a Regex from pattern");
+/// Builds the rules of the normalized statements.
+pub(super) fn compile<'a>(
+    statements: impl IntoIterator<Item = &'a Statement>,
+    mut static_rules: StaticRulesBuilder,
+    matcher: &impl ResourceMatcher,
+    substituter: &impl Substituter,
+    normalizer: &impl Normalizer,
+    filters: &Arc<Filters>,
+) -> Result<(StaticRules, VariableRules)> {
+    let mut variable_rules = Identities::new();
+    for statement in statements {
+        process_statement(statement, &mut static_rules, &mut variable_rules)?;
     }
-    VAR_PATTERN.is_match(value)
-}
 
-enum Source {
-    Json(String),
-    Definition(PolicyDefinition),
+    // prefix matching is indexed, other matchers are called for every resource.
+    let index = match matcher.semantics() {
+        Some(MatchSemantics::StartsWith) => Some(PrefixIndex::Bytes),
+        Some(MatchSemantics::PathPrefix) => Some(PrefixIndex::Paths),
+        _ => None,
+    };
+    let static_rules = static_rules.build(index)?;
+    Ok((
+        static_rules,
+        VariableRules::compile(variable_rules, substituter, normalizer, filters)?,
+    ))
 }
 
-/// Represents a deserialized policy definition.
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct PolicyDefinition {
-    pub(super) statements: Vec<Statement>,
+/// Normalizes the literal values of the statement.
+///
+/// Values with variables are normalized after they are rendered, so that their literal
+/// parts are normalized once, like request values. Normalized literals are escaped to not
+/// turn into variables, e.g. `%7B%7Bany%7D%7D` with percent-decoding.
+pub(super) fn normalize_statement(statement: &mut Statement, normalizer: &impl Normalizer) {
+    fn normalize(values: &mut [String], normalize: impl Fn(&str) -> Cow<'_, str>) {
+        for value in values {
+            // malformed values are reported by validation.
+            if tokenizer::validate(value).is_err() {
+                continue;
+            }
+            if let Some(literal) = tokenizer::literal(value) {
+                let normalized = normalize(&literal);
+                let escaped = tokenizer::escape(&normalized);
+                if escaped != value.as_str() {
+                    *value = escaped.into_owned();
+                }
+            }
+        }
+    }
+    normalize(&mut statement.identities, |value| {
+        normalizer.normalize_identity(value)
+    });
+    normalize(&mut statement.operations, |value| {
+        normalizer.normalize_operation(value)
+    });
+    normalize(&mut statement.resources, |value| {
+        normalizer.normalize_resource(value)
+    });
 }
 
-impl PolicyDefinition {
-    pub fn from_json(json: &str) -> Result<Self> {
-        let definition: PolicyDefinition =
-            serde_json::from_str(json).map_err(Error::Deserializing)?;
-
-        Ok(definition)
-    }
+fn process_statement(
+    statement: &Statement,
+    static_rules: &mut StaticRulesBuilder,
+    variable_rules: &mut Identities,
+) -> Result<()> {
+    let (static_res, variable_res) = process_resources(statement);
+    // static resources are stored once and shared by all static identities and operations.
+    let resources = static_rules.add_resources(
+        static_res
+            .0
+            .iter()
+            .map(|(resource, effect)| (resource.as_str(), *effect)),
+    )?;
+    let mut all_res = static_res;
+    all_res.merge(variable_res.clone());
 
-    pub fn statements(&self) -> &Vec<Statement> {
-        &self.statements
+    for identity in &statement.identities {
+        let identity_literal = tokenizer::literal(identity);
+        let mut operations = Operations::new();
+        for operation in &statement.operations {
+            match (&identity_literal, tokenizer::literal(operation)) {
+                // divide static resources and variable resources
+                // between static rules and variable rules.
+                (Some(identity), Some(operation_literal)) => {
+                    if let Some(resources) = resources {
+                        static_rules.insert(identity, &operation_literal, resources)?;
+                    }
+                    operations.insert(operation, variable_res.clone());
+                }
+                // if current identity or operation has variables,
+                // then all resources need to be cloned into variable rules.
+                _ => operations.insert(operation, all_res.clone()),
+            }
+        }
+        variable_rules.insert(identity, operations);
     }
+    Ok(())
 }
 
-/// Represents a statement in a policy definition.
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Statement {
-    #[serde(default)]
-    pub(super) order: usize,
-    #[serde(default)]
-    pub(super) description: String,
-    pub(super) effect: Effect,
-    pub(super) identities: Vec<String>,
-    pub(super) operations: Vec<String>,
-    #[serde(default)]
-    pub(super) resources: Vec<String>,
-}
-
-impl Statement {
-    pub(crate) fn order(&self) -> usize {
-        self.order
+fn process_resources(statement: &Statement) -> (Resources, Resources) {
+    let mut static_res = Resources::new();
+    let mut variable_res = Resources::new();
+    if statement.resources.is_empty() {
+        static_res.insert("", statement.into());
     }
 
-    pub fn description(&self) -> &str {
-        &self.description
+    for resource in &statement.resources {
+        // split resources into two buckets - static or variable rules:
+        match tokenizer::literal(resource) {
+            Some(literal) => static_res.insert(&literal, statement.into()),
+            None => variable_res.insert(resource, statement.into()),
+        }
     }
 
-    pub fn effect(&self) -> Effect {
-        self.effect
-    }
+    (static_res, variable_res)
+}
 
-    pub fn identities(&self) -> &Vec<String> {
-        &self.identities
-    }
+/// Returns the values of the statement that may contain variables, with their fields.
+fn values(statement: &Statement) -> [(Field, &Vec<String>); 3] {
+    [
+        (Field::Identities, statement.identities()),
+        (Field::Operations, statement.operations()),
+        (Field::Resources, statement.resources()),
+    ]
+}
 
-    pub fn operations(&self) -> &Vec<String> {
-        &self.operations
+/// Reports statement values with malformed variables.
+fn visit_malformed_variables(definition: &PolicyDefinition) -> Vec<Diagnostic> {
+    let mut result = vec![];
+    for statement in definition.statements() {
+        for (field, values) in values(statement) {
+            for (index, value) in values.iter().enumerate() {
+                if let Err(error) = tokenizer::validate(value) {
+                    result.push(
+                        Diagnostic::new(statement, field, error.to_string())
+                            .with_value(index, value.as_str())
+                            .with_code(Lint::UnbalancedBraces.id()),
+                    );
+                }
+            }
+        }
     }
+    result
+}
 
-    pub fn resources(&self) -> &Vec<String> {
-        &self.resources
+/// Reports variables and filters of statement values that the substituter does not support.
+/// Custom filters of the builder are supported too.
+fn visit_variables<S: Substituter>(
+    definition: &PolicyDefinition,
+    substituter: &S,
+    custom_filters: &Filters,
+) -> Vec<Diagnostic> {
+    let filters = substituter.filters().map(|mut filters| {
+        filters.extend(custom_filters.names());
+        filters
+    });
+    let mut result = vec![];
+    for statement in definition.statements() {
+        for (field, values) in values(statement) {
+            let supported = substituter.variables(field);
+            for (index, value) in values.iter().enumerate() {
+                for variable in tokenizer::variables(value) {
+                    let expression = Expression::parse(variable);
+                    let mut messages = vec![];
+                    if let Some(supported) = supported {
+                        if !is_supported(supported, expression.name()) {
+                            messages.push(format!(
+                                "Variable {variable} is not supported by the substituter"
+                            ));
+                        }
+                    }
+                    if let Some(filters) = &filters {
+                        for (filter, _) in expression.filters() {
+                            if !filters.contains(&filter) {
+                                messages.push(format!(
+                                    "Filter {filter} of variable {variable} is not supported by the substituter"
+                                ));
+                            }
+                        }
+                    }
+                    result.extend(messages.into_iter().map(|message| {
+                        Diagnostic::new(statement, field, message)
+                            .with_value(index, value.as_str())
+                            .with_code(Lint::UnknownVariable.id())
+                    }));
+                }
+            }
+        }
     }
+    result
 }
 
-/// Represents an effect on a statement.
-#[derive(Debug, Deserialize, Copy, Clone, PartialOrd, PartialEq)]
-#[serde(rename_all = "camelCase")]
-pub enum Effect {
-    Allow,
-    Deny,
+enum Source {
+    Json(String),
+    #[cfg(feature = "yaml")]
+    Yaml(String),
+    #[cfg(feature = "toml")]
+    Toml(String),
+    Definition(PolicyDefinition),
+    Dir(PathBuf),
 }
 
 #[cfg(test)]
@@ -322,7 +630,9 @@ mod tests {
 
     use crate::{
         core::{tests::build_policy, Effect, EffectOrd},
+        filter,
         validator::ValidatorError,
+        Request,
     };
 
     use super::*;
@@ -371,10 +681,14 @@ mod tests {
             ]
         }"#;
 
-        let policy = build_policy(json);
+        let policy = PolicyBuilder::from_json(json)
+            .with_default_decision(Decision::Denied)
+            .with_unknown_variables(Severity::Ignore)
+            .build()
+            .unwrap();
 
-        assert_eq!(1, policy.variable_rules.len());
-        assert_eq!(2, policy.static_rules.len());
+        assert_eq!(1, policy.variable_rules.identities().len());
+        assert_eq!(2, policy.static_rules.identities().len());
     }
 
     #[test]
@@ -432,15 +746,19 @@ mod tests {
             ]
         }"#;
 
-        let policy = build_policy(json);
+        let policy = PolicyBuilder::from_json(json)
+            .with_default_decision(Decision::Denied)
+            .with_unknown_variables(Severity::Ignore)
+            .build()
+            .unwrap();
 
         // assert static rules have 1 identity and 2 operations
-        assert_eq!(1, policy.static_rules.len());
-        assert_eq!(2, policy.static_rules["actor_a"].0.len());
+        assert_eq!(1, policy.static_rules.identities().len());
+        assert_eq!(2, policy.static_rules.operations("actor_a").len());
 
         // assert variable rules have 1 identity and 2 operations
-        assert_eq!(1, policy.variable_rules.len());
-        assert_eq!(2, policy.variable_rules["actor_a"].0.len());
+        assert_eq!(1, policy.variable_rules.identities().len());
+        assert_eq!(2, policy.variable_rules.operations("actor_a").len());
     }
 
     #[test]
@@ -498,15 +816,19 @@ mod tests {
             ]
         }"#;
 
-        let policy = build_policy(json);
+        let policy = PolicyBuilder::from_json(json)
+            .with_default_decision(Decision::Denied)
+            .with_unknown_variables(Severity::Ignore)
+            .build()
+            .unwrap();
 
         // assert static rules have 1 identity, 1 operations and 2 resources
-        assert_eq!(1, policy.static_rules["actor_a"].0.len());
-        assert_eq!(2, policy.static_rules["actor_a"].0["write"].0.len());
+        assert_eq!(1, policy.static_rules.operations("actor_a").len());
+        assert_eq!(2, policy.static_rules.resources("actor_a", "write").len());
 
         // assert variable rules have 1 identity, 1 operations and 2 resources
-        assert_eq!(1, policy.variable_rules["actor_a"].0.len());
-        assert_eq!(2, policy.variable_rules["actor_a"].0["read"].0.len());
+        assert_eq!(1, policy.variable_rules.operations("actor_a").len());
+        assert_eq!(2, policy.variable_rules.resources("actor_a", "read").len());
     }
 
     #[test]
@@ -564,7 +886,11 @@ mod tests {
             ]
         }"#;
 
-        let policy = build_policy(json);
+        let policy = PolicyBuilder::from_json(json)
+            .with_default_decision(Decision::Denied)
+            .with_unknown_variables(Severity::Ignore)
+            .build()
+            .unwrap();
 
         // assert higher priority rule wins.
         assert_eq!(
@@ -572,7 +898,7 @@ mod tests {
                 order: 0,
                 effect: Effect::Allow
             },
-            policy.static_rules["actor_a"].0["write"].0["events/telemetry"]
+            policy.static_rules.resources("actor_a", "write")["events/telemetry"]
         );
 
         // assert higher priority rule wins for variable rules.
@@ -581,7 +907,7 @@ mod tests {
                 order: 2,
                 effect: Effect::Allow
             },
-            policy.variable_rules["actor_a"].0["read"].0["{{variable}}/#"]
+            policy.variable_rules.resources("actor_a", "read")["{{variable}}/#"]
         );
     }
 
@@ -609,33 +935,37 @@ mod tests {
             ]
         }"#;
 
-        let policy = build_policy(json);
+        let policy = PolicyBuilder::from_json(json)
+            .with_default_decision(Decision::Denied)
+            .with_unknown_variables(Severity::Ignore)
+            .build()
+            .unwrap();
 
         // assert static rules.
-        assert_eq!(2, policy.static_rules.len());
+        assert_eq!(2, policy.static_rules.identities().len());
         assert_eq!(
-            policy.static_rules["actor_a"].0["write"].0["events/telemetry"],
+            policy.static_rules.resources("actor_a", "write")["events/telemetry"],
             EffectOrd {
                 effect: Effect::Allow,
                 order: 0
             }
         );
         assert_eq!(
-            policy.static_rules["actor_a"].0["read"].0["events/telemetry"],
+            policy.static_rules.resources("actor_a", "read")["events/telemetry"],
             EffectOrd {
                 effect: Effect::Allow,
                 order: 0
             }
         );
         assert_eq!(
-            policy.static_rules["actor_b"].0["write"].0["events/telemetry"],
+            policy.static_rules.resources("actor_b", "write")["events/telemetry"],
             EffectOrd {
                 effect: Effect::Allow,
                 order: 0
             }
         );
         assert_eq!(
-            policy.static_rules["actor_b"].0["read"].0["events/telemetry"],
+            policy.static_rules.resources("actor_b", "read")["events/telemetry"],
             EffectOrd {
                 effect: Effect::Allow,
                 order: 0
@@ -643,44 +973,44 @@ mod tests {
         );
 
         // assert variable rules.
-        assert_eq!(3, policy.variable_rules.len());
+        assert_eq!(3, policy.variable_rules.identities().len());
         assert_eq!(
-            policy.variable_rules["actor_a"].0["write"].0["devices/{{variable}}/#"],
+            policy.variable_rules.resources("actor_a", "write")["devices/{{variable}}/#"],
             EffectOrd {
                 effect: Effect::Allow,
                 order: 0
             }
         );
         assert_eq!(
-            policy.variable_rules["actor_a"].0["read"].0["devices/{{variable}}/#"],
+            policy.variable_rules.resources("actor_a", "read")["devices/{{variable}}/#"],
             EffectOrd {
                 effect: Effect::Allow,
                 order: 0
             }
         );
         assert_eq!(
-            policy.variable_rules["actor_b"].0["write"].0["devices/{{variable}}/#"],
+            policy.variable_rules.resources("actor_b", "write")["devices/{{variable}}/#"],
             EffectOrd {
                 effect: Effect::Allow,
                 order: 0
             }
         );
         assert_eq!(
-            policy.variable_rules["actor_b"].0["read"].0["devices/{{variable}}/#"],
+            policy.variable_rules.resources("actor_b", "read")["devices/{{variable}}/#"],
             EffectOrd {
                 effect: Effect::Allow,
                 order: 0
             }
         );
         assert_eq!(
-            policy.variable_rules["{{var_actor}}"].0["write"].0["devices/{{variable}}/#"],
+            policy.variable_rules.resources("{{var_actor}}", "write")["devices/{{variable}}/#"],
             EffectOrd {
                 effect: Effect::Allow,
                 order: 0
             }
         );
         assert_eq!(
-            policy.variable_rules["{{var_actor}}"].0["read"].0["devices/{{variable}}/#"],
+            policy.variable_rules.resources("{{var_actor}}", "read")["devices/{{variable}}/#"],
             EffectOrd {
                 effect: Effect::Allow,
                 order: 0
@@ -688,6 +1018,331 @@ mod tests {
         );
     }
 
+    #[test]
+    fn policy_metadata_test() {
+        let json = r#"{
+            "schemaVersion": 2,
+            "id": "policy-1",
+            "version": "2021-01-01",
+            "metadata": {
+                "owner": "team-a",
+                "tags": [ "mqtt" ]
+            },
+            "statements": [
+                {
+                    "sid": "allow-telemetry",
+                    "effect": "allow",
+                    "identities": [
+                        "actor_a"
+                    ],
+                    "operations": [
+                        "write"
+                    ],
+                    "resources": [
+                        "events/telemetry"
+                    ]
+                },
+                {
+                    "effect": "deny",
+                    "identities": [
+                        "actor_b"
+                    ],
+                    "operations": [
+                        "write"
+                    ]
+                }
+            ]
+        }"#;
+
+        let policy = build_policy(json);
+
+        assert_eq!(Some("policy-1"), policy.id());
+        assert_eq!(Some("2021-01-01"), policy.version());
+        assert_eq!("team-a", policy.metadata()["owner"]);
+        assert_eq!(Some("allow-telemetry"), policy.sid(0));
+        assert_eq!(None, policy.sid(1));
+        assert_eq!(None, policy.sid(2));
+    }
+
+    #[test]
+    fn duplicate_sid_fails_build_test() {
+        let json = r#"{
+            "schemaVersion": 2,
+            "statements": [
+                {
+                    "sid": "telemetry",
+                    "effect": "allow",
+                    "identities": [
+                        "actor_a"
+                    ],
+                    "operations": [
+                        "write"
+                    ]
+                },
+                {
+                    "sid": "telemetry",
+                    "effect": "deny",
+                    "identities": [
+                        "actor_b"
+                    ],
+                    "operations": [
+                        "write"
+                    ]
+                }
+            ]
+        }"#;
+
+        let result = PolicyBuilder::from_json(json).build();
+
+        assert_matches!(result, Err(Error::Validation(_)));
+
+        // ids are unique regardless of the validator.
+        let result = PolicyBuilder::from_json(json)
+            .with_validator(crate::Linter::new(MatchSemantics::Equality))
+            .build();
+
+        let error = assert_matches!(result, Err(Error::Validation(e)) => e);
+        assert!(
+            error.to_string().contains("already used by statement #0"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn unknown_variable_fails_build_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource("/home/{{idenity}}/")
+            })
+            .build();
+
+        let result = PolicyBuilder::from_definition(definition)
+            .with_unknown_variables(Severity::Fail)
+            .build();
+
+        let error = assert_matches!(result, Err(Error::Validation(e)) => e);
+        assert_eq!(
+            "statement #0, /statements/0/resources/0: [unknown-variable] \
+             Variable {{idenity}} is not supported by the substituter (value: \"/home/{{idenity}}/\")",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn malformed_variable_fails_build_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource("/home/{{identity")
+            })
+            .build();
+
+        let result = PolicyBuilder::from_definition(definition).build();
+
+        let error = assert_matches!(result, Err(Error::Validation(e)) => e);
+        assert_eq!(
+            "statement #0, /statements/0/resources/0: [unbalanced-braces] \
+             Variable at position 6 is not closed with }} (value: \"/home/{{identity\")",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn escaped_braces_are_literal_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource(r"/raw/\{{any}}")
+            })
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource(r"/{{identity}}/\{{any}}")
+            })
+            .build();
+
+        let policy = PolicyBuilder::from_definition(definition).build().unwrap();
+
+        assert_eq!(1, policy.static_rules.identities().len());
+        let request = Request::new("actor_a", "read", "/raw/{{any}}").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+        let request = Request::new("actor_a", "read", "/raw/other").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Denied));
+        let request = Request::new("actor_a", "read", "/actor_a/{{any}}").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+        let request = Request::new("actor_a", "read", "/actor_a/other").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Denied));
+    }
+
+    #[test]
+    fn unknown_variable_warning_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("{{operation}}")
+                    .operation("read")
+                    .resource("/home/")
+            })
+            .build();
+
+        let policy = PolicyBuilder::from_definition(definition)
+            .with_unknown_variables(Severity::Warn)
+            .build()
+            .unwrap();
+
+        assert_eq!(1, policy.warnings().len());
+        assert_eq!("/statements/0/identities/0", policy.warnings()[0].path());
+    }
+
+    #[test]
+    fn variable_filters_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("alice@corp")
+                    .operation("read")
+                    .resource("/home/{{identity|split:@|first}}/")
+            })
+            .statement(|s| {
+                s.allow()
+                    .identity("alice@corp")
+                    .operation("read")
+                    .resource("/tenants/{{tenant|default:public}}/")
+            })
+            .statement(|s| {
+                s.allow()
+                    .identity("alice@corp")
+                    .operation("read")
+                    .resource("/reverse/{{identity|reverse}}/")
+            })
+            .build();
+
+        let policy = PolicyBuilder::from_definition(definition)
+            .with_filter("reverse", |value, _: Option<&str>| {
+                Ok(match value {
+                    filter::Value::Str(s) => filter::Value::Str(s.chars().rev().collect()),
+                    value => value,
+                })
+            })
+            .build()
+            .unwrap();
+
+        let request = Request::new("alice@corp", "read", "/home/alice/").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+        let request = Request::new("alice@corp", "read", "/home/alice@corp/").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Denied));
+        let request = Request::new("alice@corp", "read", "/tenants/public/").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+        let request = Request::new("alice@corp", "read", "/reverse/proc@ecila/").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+        // the name of a variable with a default is checked too.
+        assert_eq!(1, policy.warnings().len());
+        assert_eq!("/statements/1/resources/0", policy.warnings()[0].path());
+    }
+
+    #[test]
+    fn unknown_variable_with_default_fails_build_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource("/home/{{idenity|default:x}}/")
+            })
+            .build();
+
+        let result = PolicyBuilder::from_definition(definition)
+            .with_unknown_variables(Severity::Fail)
+            .build();
+
+        assert_matches!(result, Err(Error::Validation(_)));
+    }
+
+    #[test]
+    fn unknown_filter_fails_build_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource("/home/{{identity|lowercase}}/")
+            })
+            .build();
+
+        let result = PolicyBuilder::from_definition(definition)
+            .with_unknown_variables(Severity::Fail)
+            .build();
+
+        let error = assert_matches!(result, Err(Error::Validation(e)) => e);
+        assert_eq!(
+            "statement #0, /statements/0/resources/0: [unknown-variable] \
+             Filter lowercase of variable {{identity|lowercase}} is not supported by the substituter \
+             (value: \"/home/{{identity|lowercase}}/\")",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn substituter_without_variables_skips_check_test() {
+        struct AnySubstituter;
+
+        impl Substituter for AnySubstituter {
+            type Context = ();
+
+            fn visit_identity(&self, value: &str, _: &Request<'_, ()>) -> Result<String> {
+                Ok(value.to_owned())
+            }
+
+            fn visit_operation(&self, value: &str, _: &Request<'_, ()>) -> Result<String> {
+                Ok(value.to_owned())
+            }
+
+            fn visit_resource(&self, value: &str, _: &Request<'_, ()>) -> Result<String> {
+                Ok(value.to_owned())
+            }
+        }
+
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource("/home/{{role}}/")
+            })
+            .build();
+
+        let policy = PolicyBuilder::from_definition(definition)
+            .with_substituter(AnySubstituter)
+            .build()
+            .unwrap();
+
+        assert!(policy.warnings().is_empty());
+    }
+
+    #[test]
+    fn unsupported_schema_version_fails_build_test() {
+        let definition: PolicyDefinition = serde_json::from_str(
+            r#"{
+                "schemaVersion": 3,
+                "statements": [ ]
+            }"#,
+        )
+        .unwrap();
+
+        let result = PolicyBuilder::from_definition(definition).build();
+
+        assert_matches!(result, Err(Error::UnsupportedSchemaVersion(3)));
+    }
+
     #[test]
     fn policy_validation_test() {
         let json = r#"{
@@ -734,7 +1389,28 @@ mod tests {
         type Error = ValidatorError;
 
         fn validate(&self, _definition: &PolicyDefinition) -> StdResult<(), Self::Error> {
-            Err(ValidatorError::ValidationSummary(vec!["error".to_string()]))
+            Err(ValidatorError::ValidationSummary(vec![]))
+        }
+    }
+
+    #[test]
+    fn local_validator_test() {
+        // a validator that is not `Send` can build a policy, but is not kept for updates.
+        let result = PolicyBuilder::from_json(r#"{ "statements": [ ] }"#)
+            .with_validator(LocalValidator(std::rc::Rc::from("invalid policy")))
+            .build();
+
+        assert_matches!(result, Err(Error::Validation(e)) if e.to_string() == "invalid policy");
+    }
+
+    #[derive(Debug)]
+    struct LocalValidator(std::rc::Rc<str>);
+
+    impl PolicyValidator for LocalValidator {
+        type Error = std::io::Error;
+
+        fn validate(&self, _definition: &PolicyDefinition) -> StdResult<(), Self::Error> {
+            Err(std::io::Error::other(self.0.to_string()))
         }
     }
 }
@@ -0,0 +1,873 @@
+use std::{collections::BTreeMap, ffi::OsStr, fs, io, mem::size_of, path::Path, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{value::RawValue, Value};
+
+use super::versions;
+use crate::{Error, Result};
+
+/// Represents a deserialized policy definition.
+///
+/// Definitions in older schema versions are migrated to this model when parsed.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PolicyDefinition {
+    /// The schema version of the definition. Definitions without it are read as version 1.
+    #[serde(default = "unversioned")]
+    pub(super) schema_version: u32,
+    /// The policy identifier.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) id: Option<String>,
+    /// The policy version, e.g. a revision number or a date.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) version: Option<String>,
+    /// Arbitrary policy metadata.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) metadata: BTreeMap<String, Value>,
+    /// Policy statements. The first matching statement decides.
+    pub(super) statements: Vec<Statement>,
+}
+
+impl PolicyDefinition {
+    /// The latest policy definition schema version supported by this crate.
+    pub const LATEST_SCHEMA_VERSION: u32 = versions::LATEST;
+
+    /// Creates a [`PolicyDefinitionBuilder`] to construct a definition in code.
+    ///
+    /// ```rust
+    /// use allow_me::PolicyDefinition;
+    ///
+    /// let definition = PolicyDefinition::builder()
+    ///     .statement(|s| s.allow().identity("actor_a").operation("write").resource("resource_1"))
+    ///     .build();
+    ///
+    /// assert_eq!(1, definition.statements().len());
+    /// ```
+    pub fn builder() -> PolicyDefinitionBuilder {
+        PolicyDefinitionBuilder {
+            definition: PolicyDefinition {
+                schema_version: versions::LATEST,
+                id: None,
+                version: None,
+                metadata: BTreeMap::new(),
+                statements: Vec::new(),
+            },
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let mut definition = versions::parse(json, &versions::Json)?;
+
+        for (statement, line) in definition.statements.iter_mut().zip(statement_lines(json)) {
+            statement.line = Some(line);
+        }
+
+        Ok(definition.ordered())
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        versions::parse(yaml, &versions::Yaml).map(Self::ordered)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        versions::parse(toml, &versions::Toml).map(Self::ordered)
+    }
+
+    /// Reads the policy files of the directory in lexical order of their names, and
+    /// concatenates their statements.
+    ///
+    /// `.json` files are read, and `.yaml`, `.yml` or `.toml` files if the corresponding
+    /// feature is enabled. The id and version of the first file that specifies them are
+    /// used, and metadata keys of later files override earlier ones.
+    ///
+    /// # Errors
+    /// Returns [`Error::Io`] if the files can not be read, and [`Error::File`] with the
+    /// file name if a file can not be parsed.
+    pub fn from_dir(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let io = |source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let mut files = fs::read_dir(path)
+            .map_err(io)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(io)?;
+        files.sort();
+
+        let mut result = Self::builder().build();
+        for file in files {
+            let Some(parse) = Self::parser(&file) else {
+                continue;
+            };
+            if !file.is_file() {
+                continue;
+            }
+            let definition = fs::read_to_string(&file)
+                .map_err(|source| Error::Io {
+                    path: file.clone(),
+                    source,
+                })
+                .and_then(|text| parse(&text))
+                .map_err(|e| match e {
+                    e @ Error::Io { .. } => e,
+                    e => Error::File {
+                        path: file.clone(),
+                        source: Box::new(e),
+                    },
+                })?;
+
+            let source: Arc<Path> = Arc::from(file);
+            result.id = result.id.or(definition.id);
+            result.version = result.version.or(definition.version);
+            result.metadata.extend(definition.metadata);
+            result
+                .statements
+                .extend(definition.statements.into_iter().enumerate().map(
+                    |(index, mut statement)| {
+                        statement.source = Some((Arc::clone(&source), index));
+                        statement
+                    },
+                ));
+        }
+        Ok(result.ordered())
+    }
+
+    /// Returns the parser of a policy file by its extension, or `None` if
+    /// [`PolicyDefinition::from_dir`] skips the file.
+    pub(crate) fn parser(file: &Path) -> Option<fn(&str) -> Result<Self>> {
+        match file.extension().and_then(OsStr::to_str) {
+            Some("json") => Some(Self::from_json),
+            #[cfg(feature = "yaml")]
+            Some("yaml" | "yml") => Some(Self::from_yaml),
+            #[cfg(feature = "toml")]
+            Some("toml") => Some(Self::from_toml),
+            _ => None,
+        }
+    }
+
+    /// Serializes the definition into pretty-printed json.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(Error::Serializing)
+    }
+
+    /// Returns the JSON Schema of the policy definition format.
+    ///
+    /// The schema is generated from the definition types and describes the latest
+    /// schema version. [`PolicyDefinition::from_json`] also accepts unversioned documents
+    /// with unknown fields, which the schema rejects.
+    #[cfg(feature = "schema")]
+    pub fn json_schema() -> Result<Value> {
+        let schema = schemars::schema_for!(PolicyDefinition);
+        serde_json::to_value(schema).map_err(Error::Serializing)
+    }
+
+    /// The schema version of the definition.
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+
+    /// The policy identifier.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// The policy version, e.g. a revision number or a date.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Arbitrary policy metadata.
+    pub fn metadata(&self) -> &BTreeMap<String, Value> {
+        &self.metadata
+    }
+
+    pub fn statements(&self) -> &Vec<Statement> {
+        &self.statements
+    }
+
+    /// Assigns each statement its position in the definition.
+    pub(super) fn ordered(mut self) -> Self {
+        for (order, statement) in self.statements.iter_mut().enumerate() {
+            statement.order = order;
+        }
+        self
+    }
+}
+
+/// Returns the 1-based line where every statement of the json document starts.
+fn statement_lines(json: &str) -> Vec<usize> {
+    /// Borrows the statements as they are in the document.
+    #[derive(Deserialize)]
+    struct Spans<'a> {
+        #[serde(borrow)]
+        statements: Vec<&'a RawValue>,
+    }
+
+    serde_json::from_str::<Spans<'_>>(json).map_or_else(
+        |_| vec![],
+        |spans| {
+            spans
+                .statements
+                .iter()
+                .map(|statement| {
+                    let offset = statement.get().as_ptr() as usize - json.as_ptr() as usize;
+                    versions::line_column(json, offset).0
+                })
+                .collect()
+        },
+    )
+}
+
+fn unversioned() -> u32 {
+    versions::UNVERSIONED
+}
+
+/// Represents a statement in a policy definition.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Statement {
+    #[serde(skip)]
+    pub(super) order: usize,
+    /// The statement identifier, unique within the policy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) sid: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(super) description: String,
+    pub(super) effect: Effect,
+    /// Identities the statement applies to. May contain variables, e.g. `{{any}}`.
+    pub(super) identities: Vec<String>,
+    /// Operations the statement applies to.
+    pub(super) operations: Vec<String>,
+    /// Resources the statement applies to. May be omitted if the operations have no resources.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) resources: Vec<String>,
+    /// Arbitrary statement metadata, e.g. `"suppress": ["any-identity-allow"]` for the linter.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) metadata: BTreeMap<String, Value>,
+    #[serde(skip)]
+    pub(super) line: Option<usize>,
+    /// The file the statement was read from, and its index in the statements of the file.
+    #[serde(skip)]
+    pub(super) source: Option<(Arc<Path>, usize)>,
+}
+
+impl Statement {
+    /// Creates a [`StatementBuilder`] to construct a statement in code.
+    pub fn builder() -> StatementBuilder {
+        StatementBuilder::new()
+    }
+
+    pub(crate) fn order(&self) -> usize {
+        self.order
+    }
+
+    /// The index of the statement in its source file, or in the policy definition.
+    pub(crate) fn index(&self) -> usize {
+        self.source.as_ref().map_or(self.order, |(_, index)| *index)
+    }
+
+    /// Approximate heap bytes used by the statement, excluding metadata.
+    pub(crate) fn heap_size(&self) -> usize {
+        let strings = |values: &Vec<String>| {
+            values.capacity() * size_of::<String>()
+                + values.iter().map(String::capacity).sum::<usize>()
+        };
+        self.sid.as_ref().map_or(0, String::capacity)
+            + self.description.capacity()
+            + strings(&self.identities)
+            + strings(&self.operations)
+            + strings(&self.resources)
+    }
+
+    /// The line where the statement starts, if the definition was parsed from json.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// The file the statement was read from, if the definition was read from a directory.
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_ref().map(|(source, _)| &**source)
+    }
+
+    /// The statement identifier, unique within the policy.
+    pub fn sid(&self) -> Option<&str> {
+        self.sid.as_deref()
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn effect(&self) -> Effect {
+        self.effect
+    }
+
+    pub fn identities(&self) -> &Vec<String> {
+        &self.identities
+    }
+
+    pub fn operations(&self) -> &Vec<String> {
+        &self.operations
+    }
+
+    pub fn resources(&self) -> &Vec<String> {
+        &self.resources
+    }
+
+    /// Arbitrary statement metadata.
+    pub fn metadata(&self) -> &BTreeMap<String, Value> {
+        &self.metadata
+    }
+}
+
+/// Represents an effect on a statement.
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialOrd, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+/// A fluent builder for [`PolicyDefinition`].
+///
+/// Statements are added in order, so the first added statement has the highest priority.
+#[derive(Debug)]
+pub struct PolicyDefinitionBuilder {
+    definition: PolicyDefinition,
+}
+
+impl PolicyDefinitionBuilder {
+    /// Sets the policy identifier.
+    #[must_use]
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.definition.id = Some(id.into());
+        self
+    }
+
+    /// Sets the policy version.
+    #[must_use]
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.definition.version = Some(version.into());
+        self
+    }
+
+    /// Adds a metadata entry to the policy.
+    #[must_use]
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.definition.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Adds a statement configured by the provided closure.
+    #[must_use]
+    pub fn statement<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(StatementBuilder) -> StatementBuilder,
+    {
+        let builder = f(StatementBuilder::new());
+        self.definition.statements.push(builder.statement);
+        self
+    }
+
+    /// Builds the [`PolicyDefinition`].
+    pub fn build(self) -> PolicyDefinition {
+        self.definition.ordered()
+    }
+}
+
+/// A fluent builder for a single [`Statement`].
+///
+/// The effect of a statement defaults to [`Effect::Deny`].
+#[derive(Debug)]
+pub struct StatementBuilder {
+    statement: Statement,
+}
+
+impl StatementBuilder {
+    fn new() -> Self {
+        Self {
+            statement: Statement {
+                order: 0,
+                sid: None,
+                description: String::new(),
+                effect: Effect::Deny,
+                identities: Vec::new(),
+                operations: Vec::new(),
+                resources: Vec::new(),
+                metadata: BTreeMap::new(),
+                line: None,
+                source: None,
+            },
+        }
+    }
+
+    /// Sets the statement effect to [`Effect::Allow`].
+    #[must_use]
+    pub fn allow(self) -> Self {
+        self.effect(Effect::Allow)
+    }
+
+    /// Sets the statement effect to [`Effect::Deny`].
+    #[must_use]
+    pub fn deny(self) -> Self {
+        self.effect(Effect::Deny)
+    }
+
+    /// Sets the statement effect.
+    #[must_use]
+    pub fn effect(mut self, effect: Effect) -> Self {
+        self.statement.effect = effect;
+        self
+    }
+
+    /// Sets the statement identifier.
+    #[must_use]
+    pub fn sid(mut self, sid: impl Into<String>) -> Self {
+        self.statement.sid = Some(sid.into());
+        self
+    }
+
+    /// Sets the statement description.
+    #[must_use]
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.statement.description = description.into();
+        self
+    }
+
+    /// Adds a metadata entry to the statement.
+    #[must_use]
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.statement.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Adds an identity to the statement.
+    #[must_use]
+    pub fn identity(mut self, identity: impl Into<String>) -> Self {
+        self.statement.identities.push(identity.into());
+        self
+    }
+
+    /// Adds several identities to the statement.
+    #[must_use]
+    pub fn identities<I>(mut self, identities: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.statement
+            .identities
+            .extend(identities.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds an operation to the statement.
+    #[must_use]
+    pub fn operation(mut self, operation: impl Into<String>) -> Self {
+        self.statement.operations.push(operation.into());
+        self
+    }
+
+    /// Adds several operations to the statement.
+    #[must_use]
+    pub fn operations<I>(mut self, operations: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.statement
+            .operations
+            .extend(operations.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds a resource to the statement.
+    #[must_use]
+    pub fn resource(mut self, resource: impl Into<String>) -> Self {
+        self.statement.resources.push(resource.into());
+        self
+    }
+
+    /// Adds several resources to the statement.
+    #[must_use]
+    pub fn resources<I>(mut self, resources: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.statement
+            .resources
+            .extend(resources.into_iter().map(Into::into));
+        self
+    }
+
+    /// Builds the [`Statement`], e.g. to add it to a policy with [`Policy::update`](`crate::Policy::update`).
+    pub fn build(self) -> Statement {
+        self.statement
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use crate::{Decision, PolicyBuilder, Request};
+
+    use super::*;
+
+    #[cfg(feature = "schema")]
+    fn example_policies() -> Vec<(&'static str, &'static str)> {
+        let examples = [
+            (
+                "customizations",
+                include_str!("../../examples/customizations.rs"),
+            ),
+            ("json", include_str!("../../examples/json.rs")),
+            ("order", include_str!("../../examples/order.rs")),
+            ("vars", include_str!("../../examples/vars.rs")),
+        ];
+
+        examples
+            .iter()
+            .flat_map(|(name, source)| {
+                source.split("r#\"").skip(1).map(move |block| {
+                    let end = block.find("\"#").expect("unterminated raw string");
+                    (*name, &block[..end])
+                })
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn examples_match_json_schema_test() {
+        let schema =
+            jsonschema::JSONSchema::compile(&PolicyDefinition::json_schema().unwrap()).unwrap();
+        let policies = example_policies();
+        assert!(!policies.is_empty());
+
+        for (name, json) in policies {
+            let document: Value = serde_json::from_str(json).unwrap();
+            let errors = match schema.validate(&document) {
+                Ok(()) => vec![],
+                Err(errors) => errors.map(|e| e.to_string()).collect(),
+            };
+            assert!(
+                errors.is_empty(),
+                "example {} does not match the schema: {:?}",
+                name,
+                errors
+            );
+        }
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn json_schema_rejects_unknown_fields_test() {
+        let schema =
+            jsonschema::JSONSchema::compile(&PolicyDefinition::json_schema().unwrap()).unwrap();
+        let document = serde_json::json!({
+            "statements": [
+                {
+                    "effect": "allow",
+                    "identites": [ "actor_a" ],
+                    "operations": [ "write" ]
+                }
+            ]
+        });
+
+        assert!(!schema.is_valid(&document));
+
+        // unversioned documents ignore unknown fields.
+        let document = serde_json::json!({
+            "statements": [
+                {
+                    "effect": "allow",
+                    "identities": [ "actor_a" ],
+                    "operations": [ "write" ],
+                    "unknown": true
+                }
+            ]
+        });
+        assert!(PolicyDefinition::from_json(&document.to_string()).is_ok());
+        assert!(!schema.is_valid(&document));
+    }
+
+    #[test]
+    fn fluent_builder_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .description("Allow actor_a to write")
+                    .identity("actor_a")
+                    .operation("write")
+                    .resource("resource_1")
+            })
+            .statement(|s| {
+                s.identities(vec!["actor_a", "actor_b"])
+                    .operations(vec!["read", "write"])
+                    .resources(vec!["resource_1", "resource_2"])
+            })
+            .build();
+
+        assert_eq!(2, definition.statements().len());
+        assert_eq!(Effect::Allow, definition.statements()[0].effect());
+        assert_eq!(Effect::Deny, definition.statements()[1].effect());
+        assert_eq!(
+            &vec!["actor_a", "actor_b"],
+            definition.statements()[1].identities()
+        );
+
+        let policy = PolicyBuilder::from_definition(definition)
+            .with_default_decision(Decision::Allowed)
+            .build()
+            .expect("Unable to build policy from definition.");
+
+        let request = Request::new("actor_a", "write", "resource_1").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+
+        let request = Request::new("actor_b", "read", "resource_2").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Denied));
+    }
+
+    #[test]
+    fn json_round_trip_test() {
+        let definition = PolicyDefinition::builder()
+            .id("policy-1")
+            .version("2")
+            .metadata("owner", "team-a")
+            .statement(|s| {
+                s.allow()
+                    .sid("allow-actor-a-write")
+                    .description("Allow actor_a to write")
+                    .identity("actor_a")
+                    .operation("write")
+                    .resource("resource_1")
+            })
+            .statement(|s| s.deny().identity("{{any}}").operation("connect"))
+            .build();
+
+        let json = definition.to_json().unwrap();
+        let parsed = PolicyDefinition::from_json(&json).unwrap();
+
+        assert_eq!(json, parsed.to_json().unwrap());
+        assert_eq!(Some("policy-1"), parsed.id());
+        assert_eq!(Some("2"), parsed.version());
+        assert_eq!(Some(&Value::from("team-a")), parsed.metadata().get("owner"));
+        assert_eq!(Some("allow-actor-a-write"), parsed.statements()[0].sid());
+        assert_eq!(None, parsed.statements()[1].sid());
+        assert_eq!("", parsed.statements()[1].description());
+        assert!(parsed.statements()[1].resources().is_empty());
+        assert!(!json.contains("order"));
+    }
+
+    fn write_policy(dir: &Path, name: &str, sid: &str, effect: &str) {
+        let json = format!(
+            r#"{{
+                "schemaVersion": 2,
+                "id": "{name}",
+                "metadata": {{ "owner": "{name}" }},
+                "statements": [
+                    {{
+                        "sid": "{sid}",
+                        "effect": "{effect}",
+                        "identities": [ "actor_a" ],
+                        "operations": [ "write" ],
+                        "resources": [ "resource_1" ]
+                    }}
+                ]
+            }}"#
+        );
+        fs::write(dir.join(name), json).unwrap();
+    }
+
+    #[test]
+    fn from_dir_test() {
+        let dir = tempfile::tempdir().unwrap();
+        write_policy(dir.path(), "10-team-a.json", "team-a", "deny");
+        write_policy(dir.path(), "00-base.json", "base", "allow");
+        fs::write(dir.path().join("README.md"), "not a policy").unwrap();
+
+        let definition = PolicyDefinition::from_dir(dir.path()).unwrap();
+
+        assert_eq!(Some("00-base.json"), definition.id());
+        assert_eq!(
+            Some(&Value::from("10-team-a.json")),
+            definition.metadata().get("owner")
+        );
+        let statements = definition
+            .statements()
+            .iter()
+            .map(|statement| (statement.order(), statement.sid(), statement.source()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (
+                    0,
+                    Some("base"),
+                    Some(dir.path().join("00-base.json").as_path())
+                ),
+                (
+                    1,
+                    Some("team-a"),
+                    Some(dir.path().join("10-team-a.json").as_path())
+                ),
+            ],
+            statements
+        );
+
+        let policy = PolicyBuilder::from_dir(dir.path()).build().unwrap();
+        let request = Request::new("actor_a", "write", "resource_1").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+    }
+
+    #[test]
+    fn from_dir_error_test() {
+        let dir = tempfile::tempdir().unwrap();
+        write_policy(dir.path(), "00-base.json", "base", "allow");
+        fs::write(
+            dir.path().join("10-broken.json"),
+            "{\n  \"statements\": [ {",
+        )
+        .unwrap();
+
+        let error = PolicyBuilder::from_dir(dir.path()).build().unwrap_err();
+
+        assert_matches!(&error, Error::File { path, .. } if path.ends_with("10-broken.json"));
+        assert!(error.to_string().contains("10-broken.json"), "{}", error);
+        assert_eq!(Some((2, 19)), error.location());
+
+        let error = PolicyDefinition::from_dir(dir.path().join("missing")).unwrap_err();
+        assert_matches!(error, Error::Io { .. });
+    }
+
+    #[test]
+    fn from_dir_diagnostics_test() {
+        let dir = tempfile::tempdir().unwrap();
+        write_policy(dir.path(), "00-base.json", "base", "allow");
+        fs::write(
+            dir.path().join("10-team-a.json"),
+            r#"{ "statements": [ { "effect": "allow", "identities": [], "operations": [ "write" ] } ] }"#,
+        )
+        .unwrap();
+
+        let error = PolicyBuilder::from_dir(dir.path()).build().unwrap_err();
+
+        assert!(error.to_string().contains("statement #1 in "), "{}", error);
+        assert!(error.to_string().contains("10-team-a.json"), "{}", error);
+        // the pointer is relative to the file of the statement.
+        assert!(
+            error.to_string().contains(", /statements/0/identities: "),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn statement_lines_test() {
+        let json = r#"{
+            "description": "statements: [ {",
+            "statements": [
+                {
+                    "effect": "allow",
+                    "identities": [ "{\"quoted\"}" ],
+                    "operations": [ "write" ],
+                    "resources": [ "[{" ]
+                },
+                { "effect": "deny", "identities": [ "a" ], "operations": [ "b" ] }
+            ],
+            "other": [ { } ]
+        }"#;
+
+        assert_eq!(vec![4, 10], statement_lines(json));
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn yaml_definition_test() {
+        let yaml = "
+statements:
+  - effect: allow
+    identities:
+      - actor_a
+    operations:
+      - write
+    resources:
+      - resource_1
+";
+
+        let policy = PolicyBuilder::from_yaml(yaml)
+            .build()
+            .expect("Unable to build policy from yaml.");
+
+        let request = Request::new("actor_a", "write", "resource_1").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn yaml_error_location_test() {
+        let yaml = "
+statements:
+  - effect: allow
+    identities:
+      - actor_a
+    operations: write
+";
+
+        assert_matches!(
+            PolicyDefinition::from_yaml(yaml),
+            Err(Error::DeserializingYaml {
+                line: Some(6),
+                column: Some(17),
+                ..
+            })
+        );
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_definition_test() {
+        let toml = r#"
+[[statements]]
+effect = "allow"
+identities = ["actor_a"]
+operations = ["write"]
+resources = ["resource_1"]
+"#;
+
+        let policy = PolicyBuilder::from_toml(toml)
+            .build()
+            .expect("Unable to build policy from toml.");
+
+        let request = Request::new("actor_a", "write", "resource_1").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_error_location_test() {
+        let toml = r#"
+[[statements]]
+effect = "allow"
+identities = ["actor_a"]
+operations = "write"
+"#;
+
+        assert_matches!(
+            PolicyDefinition::from_toml(toml),
+            Err(Error::DeserializingToml {
+                line: Some(5),
+                column: Some(14),
+                ..
+            })
+        );
+    }
+}
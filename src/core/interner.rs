@@ -0,0 +1,152 @@
+use std::{collections::HashMap, convert::TryFrom, mem::size_of};
+
+use crate::{Error, Result};
+
+/// An interned string.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct Sym(u32);
+
+/// Interns strings while a policy is built.
+#[derive(Debug, Default)]
+pub(crate) struct Interner {
+    symbols: HashMap<String, Sym>,
+    strings: Vec<String>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, value: &str) -> Result<Sym> {
+        if let Some(sym) = self.symbols.get(value) {
+            return Ok(*sym);
+        }
+        let sym = Sym(offset(self.strings.len())?);
+        self.symbols.insert(value.to_string(), sym);
+        self.strings.push(value.to_string());
+        Ok(sym)
+    }
+
+    /// Creates an interner that keeps the symbols of the table.
+    pub fn from_symbols(symbols: &Symbols) -> Self {
+        let mut result = Self {
+            symbols: HashMap::with_capacity(symbols.len()),
+            strings: vec![String::new(); symbols.len()],
+        };
+        for sym in &*symbols.sorted {
+            let value = symbols.resolve(*sym);
+            result.symbols.insert(value.to_string(), *sym);
+            result.strings[sym.0 as usize] = value.to_string();
+        }
+        result
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    /// Packs the interned strings into a read-only table.
+    ///
+    /// # Errors
+    /// Returns [`Error::PolicyTooLarge`] if the strings exceed 4 GiB.
+    pub fn freeze(self) -> Result<Symbols> {
+        let mut buffer = String::with_capacity(self.strings.iter().map(String::len).sum());
+        let mut spans = Vec::with_capacity(self.strings.len());
+        for string in &self.strings {
+            spans.push((offset(buffer.len())?, offset(string.len())?));
+            buffer.push_str(string);
+        }
+        // the end of the last string must fit too.
+        offset(buffer.len())?;
+        let mut sorted = self.symbols.values().copied().collect::<Vec<_>>();
+        sorted.sort_by(|a, b| self.strings[a.0 as usize].cmp(&self.strings[b.0 as usize]));
+        Ok(Symbols {
+            buffer: buffer.into_boxed_str(),
+            spans: spans.into_boxed_slice(),
+            sorted: sorted.into_boxed_slice(),
+        })
+    }
+}
+
+fn offset(value: usize) -> Result<u32> {
+    u32::try_from(value).map_err(|_| Error::PolicyTooLarge("its strings exceed 4 GiB".to_string()))
+}
+
+/// Interned strings stored in a single buffer.
+#[derive(Debug, Default)]
+pub(crate) struct Symbols {
+    buffer: Box<str>,
+    /// Start and length of each string in the buffer.
+    spans: Box<[(u32, u32)]>,
+    /// Symbols sorted by their strings for lookups.
+    sorted: Box<[Sym]>,
+}
+
+impl Symbols {
+    pub fn resolve(&self, sym: Sym) -> &str {
+        let (start, len) = self.spans[sym.0 as usize];
+        &self.buffer[start as usize..(start + len) as usize]
+    }
+
+    /// Returns the symbol of the string, if it was interned.
+    pub fn get(&self, value: &str) -> Option<Sym> {
+        self.sorted
+            .binary_search_by(|sym| self.resolve(*sym).cmp(value))
+            .ok()
+            .map(|i| self.sorted[i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    /// Heap bytes used by the strings and the lookup tables.
+    pub fn heap_size(&self) -> usize {
+        self.buffer.len()
+            + self.spans.len() * size_of::<(u32, u32)>()
+            + self.sorted.len() * size_of::<Sym>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    #[test]
+    fn intern_test() {
+        let mut interner = Interner::default();
+        let b = interner.intern("b").unwrap();
+        let a = interner.intern("a").unwrap();
+        assert_eq!(b, interner.intern("b").unwrap());
+
+        let symbols = interner.freeze().unwrap();
+
+        assert_eq!(2, symbols.len());
+        assert_eq!("a", symbols.resolve(a));
+        assert_eq!("b", symbols.resolve(b));
+        assert_eq!(Some(a), symbols.get("a"));
+        assert_eq!(Some(b), symbols.get("b"));
+        assert_eq!(None, symbols.get("c"));
+        assert_eq!(None, symbols.get(""));
+    }
+
+    #[test]
+    fn offset_test() {
+        assert_matches!(offset(u32::MAX as usize), Ok(u32::MAX));
+        assert_matches!(offset(u32::MAX as usize + 1), Err(Error::PolicyTooLarge(_)));
+    }
+
+    #[test]
+    fn from_symbols_test() {
+        let mut interner = Interner::default();
+        let b = interner.intern("b").unwrap();
+        let a = interner.intern("a").unwrap();
+        let mut interner = Interner::from_symbols(&interner.freeze().unwrap());
+
+        assert_eq!(a, interner.intern("a").unwrap());
+        assert_eq!(b, interner.intern("b").unwrap());
+        let c = interner.intern("c").unwrap();
+        let symbols = interner.freeze().unwrap();
+        assert_eq!(3, symbols.len());
+        assert_eq!(Some(c), symbols.get("c"));
+    }
+}
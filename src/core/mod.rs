@@ -1,13 +1,38 @@
 use std::{
+    borrow::Cow,
+    cell::RefCell,
     cmp::Ordering,
-    collections::{btree_map::Entry, BTreeMap},
+    collections::{btree_map::Entry, BTreeMap, HashMap},
+    mem::size_of,
+    sync::Arc,
 };
 
+use serde_json::Value;
+
 use crate::errors::Result;
-use crate::{substituter::Substituter, Error, ResourceMatcher};
+use crate::matcher::MatchSemantics;
+use crate::{
+    filter::Filters, normalizer, substituter::Substituter, validator::Severity, Diagnostic, Error,
+    Field, Normalizer, ResourceMatcher, Template,
+};
 
+mod batch;
 mod builder;
-pub use builder::{Effect, PolicyBuilder, PolicyDefinition, Statement};
+mod definition;
+mod interner;
+mod static_rules;
+mod trie;
+mod update;
+mod variables;
+mod versions;
+pub use builder::PolicyBuilder;
+use builder::SharedValidator;
+pub use definition::{
+    Effect, PolicyDefinition, PolicyDefinitionBuilder, Statement, StatementBuilder,
+};
+use static_rules::{ResourceSet, StaticRules};
+pub use update::PolicyUpdate;
+use variables::{VariableRule, VariableRules};
 
 /// Policy engine. Represents a read-only set of rules and can
 /// evaluate [`Request`] based on those rules.
@@ -15,133 +40,337 @@ pub use builder::{Effect, PolicyBuilder, PolicyDefinition, Statement};
 /// Policy engine consists of two sets:
 /// - static rules
 /// - variable rules - any rule that contains variables ("{{..}}").
+///
 /// Static rules are organized in a data structure with fast querying time.
-/// Variable rules are evaluated on every request.
+/// Variable rules are compiled into [`Template`](`crate::Template`)s, indexed by operation and
+/// literal identity prefix, and the candidates are rendered on every request.
 #[derive(Debug)]
-pub struct Policy<R, S> {
+pub struct Policy<R, S, N = normalizer::Default> {
+    id: Option<String>,
+    version: Option<String>,
+    metadata: BTreeMap<String, Value>,
+    /// Normalized statements by their order, `None` if removed by an update.
+    statements: Vec<Option<Arc<Statement>>>,
+    warnings: Vec<Diagnostic>,
     default_decision: Decision,
-    resource_matcher: R,
-    substituter: S,
-    static_rules: BTreeMap<String, Operations>,
-    variable_rules: BTreeMap<String, Operations>,
+    unknown_variables: Severity,
+    validator: Option<SharedValidator>,
+    resource_matcher: Arc<R>,
+    substituter: Arc<S>,
+    normalizer: Arc<N>,
+    filters: Arc<Filters>,
+    static_rules: StaticRules,
+    variable_rules: VariableRules,
 }
 
-impl<R, S, RC> Policy<R, S>
+// `Policy` is `Send` and `Sync` when its extensions are.
+const _: () = {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[allow(dead_code)]
+    fn assert_policy<R: Send + Sync, S: Send + Sync, N: Send + Sync>() {
+        assert_send_sync::<Policy<R, S, N>>();
+    }
+};
+
+impl<R, S, N> Policy<R, S, N> {
+    /// The policy identifier from the policy definition.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// The policy version from the policy definition.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Arbitrary policy metadata from the policy definition.
+    pub fn metadata(&self) -> &BTreeMap<String, Value> {
+        &self.metadata
+    }
+
+    /// The id of the statement at the given position in the policy definition.
+    pub fn sid(&self, statement: usize) -> Option<&str> {
+        self.statements.get(statement)?.as_ref()?.sid()
+    }
+
+    /// Problems reported by the validator that did not fail the policy construction.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// Approximate heap memory used by the policy rules.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let (strings, string_bytes) = self.static_rules.strings();
+        MemoryUsage {
+            strings,
+            string_bytes,
+            static_rules: self.static_rules.heap_size(),
+            variable_rules: self.variable_rules.heap_size(),
+            statements: self.statements.capacity() * size_of::<Option<Arc<Statement>>>()
+                + self
+                    .statements
+                    .iter()
+                    .flatten()
+                    .map(|statement| size_of::<Statement>() + statement.heap_size())
+                    .sum::<usize>(),
+        }
+    }
+}
+
+impl<R, S, N, RC> Policy<R, S, N>
 where
     R: ResourceMatcher<Context = RC>,
     S: Substituter<Context = RC>,
+    N: Normalizer,
 {
     /// Evaluates the provided [`Request`] and produces the [`Decision`].
     ///
     /// If no rules match the Request - [the default `Decision`](`PolicyBuilder::with_default_decision`) is returned.
-    pub fn evaluate(&self, request: &Request<RC>) -> Result<Decision> {
-        match self.eval_static_rules(request) {
-            // static rules undefined. Need to check variable rules.
-            Ok(None) => match self.eval_variable_rules(request) {
-                // variable rules undefined as well. Return default decision.
-                Ok(None) => Ok(self.default_decision),
-                // variable rules defined. Return the decision.
-                Ok(Some(effect)) => Ok(effect.into()),
-                Err(e) => Err(e),
-            },
-            // static rules are defined. Evaluate variable rules and compare priority.
-            Ok(Some(static_effect)) => {
-                match self.eval_variable_rules(request) {
-                    // variable rules undefined. Proceed with static rule decision.
-                    Ok(None) => Ok(static_effect.into()),
-                    // variable rules defined. Compare priority and return.
-                    Ok(Some(variable_effect)) => {
-                        // compare order.
-                        Ok(if variable_effect > static_effect {
-                            static_effect
-                        } else {
-                            variable_effect
-                        }
-                        .into())
-                    }
-                    Err(e) => Err(e),
-                }
+    pub fn evaluate(&self, request: &Request<'_, RC>) -> Result<Decision> {
+        let values = Values {
+            identity: self.normalizer.normalize_identity(&request.identity),
+            operation: self.normalizer.normalize_operation(&request.operation),
+            resource: self.normalizer.normalize_resource(&request.resource),
+        };
+        let rules = Rules {
+            // lookup an identity and an operation.
+            static_resources: self.static_rules.get(&values.identity, &values.operation),
+            candidates: self
+                .variable_rules
+                .candidates(&values.identity, &values.operation),
+        };
+        self.eval_rules(request, &values, rules, None)
+    }
+
+    /// Returns `true` if the decision for the request may depend on anything besides its
+    /// identity, operation and resource, see [`Substituter::is_volatile`].
+    pub(crate) fn is_volatile(&self, request: &Request<'_, RC>) -> bool {
+        let identity = self.normalizer.normalize_identity(&request.identity);
+        let operation = self.normalizer.normalize_operation(&request.operation);
+        let volatile = self
+            .variable_rules
+            .candidates(&identity, &operation)
+            .any(|rule| rule.volatile);
+        volatile
+    }
+
+    fn eval_rules<'p>(
+        &'p self,
+        request: &Request<'_, RC>,
+        values: &Values<'_>,
+        rules: Rules<'p, impl Iterator<Item = &'p VariableRule>>,
+        renders: Option<&mut Renders>,
+    ) -> Result<Decision> {
+        let static_effect = rules
+            .static_resources
+            .and_then(|resources| self.eval_static_rules(request, values, resources));
+        // variable rules are evaluated even if static rules are defined, to compare priority.
+        let variable_effect =
+            self.eval_variable_rules(request, values, rules.candidates, renders)?;
+
+        Ok(match (static_effect, variable_effect) {
+            // no rules are defined. Return default decision.
+            (None, None) => self.default_decision,
+            (Some(effect), None) | (None, Some(effect)) => effect.into(),
+            // both rules are defined. Compare order.
+            (Some(static_effect), Some(variable_effect)) => if variable_effect > static_effect {
+                static_effect
+            } else {
+                variable_effect
             }
+            .into(),
+        })
+    }
 
-            Err(e) => Err(e),
+    fn eval_static_rules(
+        &self,
+        request: &Request<'_, RC>,
+        values: &Values<'_>,
+        resources: &ResourceSet,
+    ) -> Option<EffectOrd> {
+        // resources are indexed for prefix matching.
+        if let Some(index) = resources.index() {
+            return index.find(&values.resource);
+        }
+        // the matcher compares resources for equality.
+        if self.resource_matcher.semantics() == Some(MatchSemantics::Equality) {
+            return self.static_rules.find_equal(resources, &values.resource);
+        }
+        // iterate over and match resources.
+        // we need to go through all resources and find one with highest priority (smallest order).
+        let mut result: Option<EffectOrd> = None;
+        for (resource, effect) in self.static_rules.iter(resources) {
+            // check the order first
+            if effect.order < result.map_or(usize::MAX, |e| e.order)
+                // only then check that matches
+                && self
+                    .resource_matcher
+                    .do_match(request, &values.resource, resource)
+            {
+                result = Some(effect);
+            }
         }
+        result
     }
 
-    fn eval_static_rules(&self, request: &Request<RC>) -> Result<Option<EffectOrd>> {
-        // lookup an identity
-        match self.static_rules.get(&request.identity) {
-            // identity exists. Look up operations.
-            Some(operations) => match operations.0.get(&request.operation) {
-                // operation exists.
-                Some(resources) => {
-                    // iterate over and match resources.
-                    // we need to go through all resources and find one with highest priority (smallest order).
-                    let mut result: Option<EffectOrd> = None;
-                    for (resource, effect) in &resources.0 {
-                        // check the order first
-                        if effect.order < result.map_or(usize::MAX, |e| e.order)
-                             // only then check that matches
-                            && self.resource_matcher.do_match( // only then check that matches
-                                request,
-                                &request.resource,
-                                &resource,
-                            )
-                        {
-                            result = Some(*effect);
-                        }
-                    }
-                    Ok(result)
-                }
-                None => Ok(None),
-            },
-            None => Ok(None),
-        }
+    fn eval_variable_rules<'p>(
+        &'p self,
+        request: &Request<'_, RC>,
+        values: &Values<'_>,
+        candidates: impl Iterator<Item = &'p VariableRule>,
+        renders: Option<&mut Renders>,
+    ) -> Result<Option<EffectOrd>> {
+        BUFFERS.with(|buffers| match buffers.try_borrow_mut() {
+            Ok(mut buffers) => {
+                let (identity, resource) = &mut *buffers;
+                self.match_variable_rules(request, values, candidates, renders, identity, resource)
+            }
+            // the matcher or the substituter evaluates another policy on this thread.
+            Err(_) => self.match_variable_rules(
+                request,
+                values,
+                candidates,
+                renders,
+                &mut String::new(),
+                &mut String::new(),
+            ),
+        })
     }
 
-    fn eval_variable_rules(&self, request: &Request<RC>) -> Result<Option<EffectOrd>> {
-        for (identity, operations) in &self.variable_rules {
-            // process identity variables.
-            let identity = self.substituter.visit_identity(identity, request)?;
+    fn match_variable_rules<'p>(
+        &'p self,
+        request: &Request<'_, RC>,
+        values: &Values<'_>,
+        candidates: impl Iterator<Item = &'p VariableRule>,
+        mut renders: Option<&mut Renders>,
+        identity_buffer: &mut String,
+        resource_buffer: &mut String,
+    ) -> Result<Option<EffectOrd>> {
+        // we need to go through all candidate rules and find one with highest priority (smallest order).
+        let mut result: Option<EffectOrd> = None;
+        for rule in candidates {
+            // check the order first
+            if rule.order >= result.map_or(usize::MAX, |e| e.order) {
+                continue;
+            }
+            // process identity variables, unless rendered for another request of the batch.
+            let key = Arc::as_ptr(&rule.identity);
+            let cached = renders
+                .as_ref()
+                .and_then(|renders| renders.identities.get(&key).copied());
+            let matches = if let Some(matches) = cached {
+                matches
+            } else {
+                identity_buffer.clear();
+                self.substituter
+                    .render_identity(&rule.identity, request, identity_buffer)?;
+                let matches =
+                    self.normalizer.normalize_identity(identity_buffer) == values.identity;
+                if let Some(renders) = renders.as_mut() {
+                    if !self
+                        .substituter
+                        .reads_resource(Field::Identities, &rule.identity)
+                    {
+                        renders.identities.insert(key, matches);
+                    }
+                }
+                matches
+            };
             // check if it does match after processing variables.
-            if identity == request.identity {
-                // lookup operation.
-                return match operations.0.get(&request.operation) {
-                    // operation exists.
-                    Some(resources) => {
-                        // iterate over and match resources.
-                        // we need to go through all resources and find one with highest priority (smallest order).
-                        let mut result: Option<EffectOrd> = None;
-                        for (resource, effect) in &resources.0 {
-                            let resource = self.substituter.visit_resource(resource, request)?;
-                            // check the order first
-                            if effect.order < result.map_or(usize::MAX, |e| e.order)
-                                // only then check that matches
-                                && self.resource_matcher.do_match(
-                                    request,
-                                    &request.resource,
-                                    &resource,
-                                )
-                            {
-                                result = Some(*effect);
-                            }
-                        }
-                        // continue to look for other identity variable rules
-                        // if no resources matched the current one.
-                        if result == None {
-                            continue;
+            if !matches {
+                continue;
+            }
+            // iterate over and match resources.
+            for (resource, effect) in &rule.resources {
+                // check the order first
+                if effect.order >= result.map_or(usize::MAX, |e| e.order) {
+                    continue;
+                }
+                // only then render and check that matches
+                let key: *const Template = resource;
+                let cached = renders
+                    .as_ref()
+                    .and_then(|renders| renders.resources.get(&key));
+                let matches = if let Some(rendered) = cached {
+                    self.resource_matcher
+                        .do_match(request, &values.resource, rendered)
+                } else {
+                    resource_buffer.clear();
+                    self.substituter
+                        .render_resource(resource, request, resource_buffer)?;
+                    let rendered = self.normalizer.normalize_resource(resource_buffer);
+                    let matches =
+                        self.resource_matcher
+                            .do_match(request, &values.resource, &rendered);
+                    if let Some(renders) = renders.as_mut() {
+                        if !self.substituter.reads_resource(Field::Resources, resource) {
+                            renders.resources.insert(key, rendered.into_owned());
                         }
-                        Ok(result)
                     }
-                    // continue to look for other identity variable rules
-                    // if no operation found for the current one.
-                    None => continue,
+                    matches
                 };
+                if matches {
+                    result = Some(*effect);
+                }
             }
         }
-        Ok(None)
+        Ok(result)
+    }
+}
+
+/// Rules of the identity and operation of a request.
+struct Rules<'p, C> {
+    static_resources: Option<&'p ResourceSet>,
+    /// Variable rules that may match the identity.
+    candidates: C,
+}
+
+/// Rendered templates that do not read the request resource, shared by requests of a batch
+/// with the same identity and operation and without a context.
+#[derive(Default)]
+struct Renders {
+    /// Whether the rendered identity template matches the identity.
+    identities: HashMap<*const Template, bool>,
+    /// Rendered and normalized resource templates.
+    resources: HashMap<*const Template, String>,
+}
+
+thread_local! {
+    /// Identity and resource buffers for rendering variable rules, reused across requests.
+    static BUFFERS: RefCell<(String, String)> = const {
+        RefCell::new((String::new(), String::new()))
+    };
+}
+
+/// Approximate heap memory used by the rules of a [`Policy`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The number of distinct strings of static rules.
+    pub strings: usize,
+    /// Bytes used by the strings of static rules.
+    pub string_bytes: usize,
+    /// Bytes used by static rules, excluding strings.
+    pub static_rules: usize,
+    /// Bytes used by variable rules, including strings.
+    pub variable_rules: usize,
+    /// Bytes used by the statements that are kept for incremental updates, excluding metadata.
+    pub statements: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.string_bytes + self.static_rules + self.variable_rules + self.statements
     }
 }
 
+/// Normalized request values.
+struct Values<'a> {
+    identity: Cow<'a, str>,
+    operation: Cow<'a, str>,
+    resource: Cow<'a, str>,
+}
+
 #[derive(Debug, Clone)]
 struct Identities(BTreeMap<String, Operations>);
 
@@ -150,12 +379,6 @@ impl Identities {
         Identities(BTreeMap::new())
     }
 
-    pub fn merge(&mut self, collection: Identities) {
-        for (key, value) in collection.0 {
-            self.insert(&key, value);
-        }
-    }
-
     fn insert(&mut self, operation: &str, resources: Operations) {
         if !resources.0.is_empty() {
             let entry = self.0.entry(operation.to_string());
@@ -234,24 +457,27 @@ impl From<BTreeMap<String, EffectOrd>> for Resources {
 }
 
 /// Represents a request that needs to be evaluated by [`Policy`] engine.
-#[derive(Debug)]
-pub struct Request<RC> {
-    identity: String,
-    operation: String,
-    resource: String,
+///
+/// The request borrows its values if it is created from `&str`, so evaluating it
+/// does not allocate. Use [`Request::into_owned`] to keep the request longer.
+#[derive(Debug, Clone)]
+pub struct Request<'a, RC> {
+    identity: Cow<'a, str>,
+    operation: Cow<'a, str>,
+    resource: Cow<'a, str>,
 
     /// Optional request context that can be used for request processing.
     context: Option<RC>,
 }
 
-impl<RC> Request<RC> {
+impl<'a, RC> Request<'a, RC> {
     /// Creates a new [`Request`].
     /// # Errors
     /// Returns an error if either identity or operation is an empty string.
     pub fn new(
-        identity: impl Into<String>,
-        operation: impl Into<String>,
-        resource: impl Into<String>,
+        identity: impl Into<Cow<'a, str>>,
+        operation: impl Into<Cow<'a, str>>,
+        resource: impl Into<Cow<'a, str>>,
     ) -> Result<Self> {
         Self::create(identity, operation, resource, None)
     }
@@ -260,18 +486,18 @@ impl<RC> Request<RC> {
     /// # Errors
     /// Returns an error if either identity or operation is an empty string.
     pub fn with_context(
-        identity: impl Into<String>,
-        operation: impl Into<String>,
-        resource: impl Into<String>,
+        identity: impl Into<Cow<'a, str>>,
+        operation: impl Into<Cow<'a, str>>,
+        resource: impl Into<Cow<'a, str>>,
         context: RC,
     ) -> Result<Self> {
         Self::create(identity, operation, resource, Some(context))
     }
 
     fn create(
-        identity: impl Into<String>,
-        operation: impl Into<String>,
-        resource: impl Into<String>,
+        identity: impl Into<Cow<'a, str>>,
+        operation: impl Into<Cow<'a, str>>,
+        resource: impl Into<Cow<'a, str>>,
         context: Option<RC>,
     ) -> Result<Self> {
         let (identity, operation, resource) = (identity.into(), operation.into(), resource.into());
@@ -292,6 +518,16 @@ impl<RC> Request<RC> {
         })
     }
 
+    /// Converts the request into one that owns its values.
+    pub fn into_owned(self) -> Request<'static, RC> {
+        Request {
+            identity: Cow::Owned(self.identity.into_owned()),
+            operation: Cow::Owned(self.operation.into_owned()),
+            resource: Cow::Owned(self.resource.into_owned()),
+            context: self.context,
+        }
+    }
+
     pub fn identity(&self) -> &str {
         &self.identity
     }
@@ -355,8 +591,8 @@ impl From<EffectOrd> for Decision {
 impl From<&Statement> for EffectOrd {
     fn from(statement: &Statement) -> Self {
         match statement.effect() {
-            builder::Effect::Allow => EffectOrd::new(Effect::Allow, statement.order()),
-            builder::Effect::Deny => EffectOrd::new(Effect::Deny, statement.order()),
+            Effect::Allow => EffectOrd::new(Effect::Allow, statement.order()),
+            Effect::Deny => EffectOrd::new(Effect::Deny, statement.order()),
         }
     }
 }
@@ -368,6 +604,7 @@ pub(crate) mod tests {
     use crate::{matcher::Default, DefaultSubstituter};
 
     use assert_matches::assert_matches;
+    use test_case::test_case;
 
     /// Helper method to build a policy.
     /// Used in both policy and builder tests.
@@ -620,7 +857,7 @@ pub(crate) mod tests {
     /// This case is created as a result of a discovered bug.
     #[test]
     fn rule_ordering_should_work_for_custom_matchers() {
-        let json = r###"{
+        let json = r#"{
             "statements": [
                 {
                     "effect": "allow",
@@ -647,7 +884,7 @@ pub(crate) mod tests {
                     ]
                 }
             ]
-        }"###;
+        }"#;
 
         let policy = PolicyBuilder::from_json(json)
             .with_default_decision(Decision::Denied)
@@ -661,10 +898,279 @@ pub(crate) mod tests {
         assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
     }
 
+    #[test]
+    fn evaluate_indexed_prefix_rules() {
+        let json = r#"{
+            "statements": [
+                {
+                    "effect": "deny",
+                    "identities": [ "actor_a" ],
+                    "operations": [ "read" ],
+                    "resources": [ "/home/alice/private" ]
+                },
+                {
+                    "effect": "allow",
+                    "identities": [ "actor_a" ],
+                    "operations": [ "read" ],
+                    "resources": [ "/home/", "/etc/hosts" ]
+                }
+            ]
+        }"#;
+
+        let policy = PolicyBuilder::from_json(json)
+            .with_matcher(crate::matcher::StartsWith)
+            .build()
+            .unwrap();
+
+        assert!(policy
+            .static_rules
+            .get("actor_a", "read")
+            .is_some_and(|resources| resources.index().is_some()));
+        for (resource, expected) in [
+            ("/home/alice/private/notes", Decision::Denied),
+            ("/home/alice/public", Decision::Allowed),
+            ("/etc/hosts.d", Decision::Allowed),
+            ("/etc/passwd", Decision::Denied),
+        ] {
+            let request = Request::new("actor_a", "read", resource).unwrap();
+            assert_eq!(expected, policy.evaluate(&request).unwrap(), "{resource}");
+        }
+    }
+
+    #[test]
+    fn evaluate_indexed_path_rules() {
+        let json = r#"{
+            "statements": [
+                {
+                    "effect": "deny",
+                    "identities": [ "actor_a" ],
+                    "operations": [ "read" ],
+                    "resources": [ "/home/alice/private" ]
+                },
+                {
+                    "effect": "allow",
+                    "identities": [ "actor_a" ],
+                    "operations": [ "read" ],
+                    "resources": [ "/home/", "/etc/hosts" ]
+                }
+            ]
+        }"#;
+
+        let policy = PolicyBuilder::from_json(json)
+            .with_matcher(crate::matcher::PathPrefix)
+            .build()
+            .unwrap();
+
+        assert!(policy
+            .static_rules
+            .get("actor_a", "read")
+            .is_some_and(|resources| resources.index().is_some()));
+        for (resource, expected) in [
+            ("/home/alice/private/notes", Decision::Denied),
+            ("/home/alice/private_notes", Decision::Allowed),
+            ("/home/alice/public/../private", Decision::Denied),
+            ("/etc/hosts", Decision::Allowed),
+            ("/etc/hosts.d", Decision::Denied),
+            ("/home/../../etc/hosts", Decision::Denied),
+        ] {
+            let request = Request::new("actor_a", "read", resource).unwrap();
+            assert_eq!(expected, policy.evaluate(&request).unwrap(), "{resource}");
+        }
+    }
+
+    #[test_case("allow", "deny", Decision::Allowed; "allow first")]
+    #[test_case("deny", "allow", Decision::Denied; "deny first")]
+    fn evaluate_variable_rules_lowest_order_wins(first: &str, second: &str, expected: Decision) {
+        // "{{any}}" identity comes first in the rules tree, but the earlier statement wins.
+        let policy = build_policy(&format!(
+            r#"{{
+                "statements": [
+                    {{
+                        "effect": "{first}",
+                        "identities": [ "{{{{identity}}}}" ],
+                        "operations": [ "read" ],
+                        "resources": [ "{{{{identity}}}}/a" ]
+                    }},
+                    {{
+                        "effect": "{second}",
+                        "identities": [ "{{{{any}}}}" ],
+                        "operations": [ "read" ],
+                        "resources": [ "{{{{identity}}}}/a" ]
+                    }}
+                ]
+            }}"#
+        ));
+
+        let request = Request::new("actor_a", "read", "actor_a/a").unwrap();
+        assert_eq!(expected, policy.evaluate(&request).unwrap());
+    }
+
+    #[test]
+    fn variable_rules_candidates_by_identity_prefix_and_suffix() {
+        let policy = build_policy(
+            r#"{
+                "statements": [
+                    {
+                        "effect": "allow",
+                        "identities": [ "tenant-{{any}}@corp" ],
+                        "operations": [ "read" ],
+                        "resources": [ "{{identity}}" ]
+                    }
+                ]
+            }"#,
+        );
+
+        assert_eq!(
+            1,
+            policy
+                .variable_rules
+                .candidates("tenant-a@corp", "read")
+                .count()
+        );
+        assert_eq!(
+            0,
+            policy.variable_rules.candidates("tenant-a", "read").count()
+        );
+        assert_eq!(
+            0,
+            policy.variable_rules.candidates("a@corp", "read").count()
+        );
+        assert_eq!(
+            0,
+            policy
+                .variable_rules
+                .candidates("tenant-a@corp", "write")
+                .count()
+        );
+    }
+
+    #[test]
+    fn memory_usage_shares_statement_resources() {
+        let identities = (0..1000).map(|i| format!("actor_{i}")).collect::<Vec<_>>();
+        let operations = (0..10).map(|i| format!("op_{i}")).collect::<Vec<_>>();
+        let resources = (0..100)
+            .map(|i| format!("resource_{i}"))
+            .collect::<Vec<_>>();
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identities(identities.clone())
+                    .operations(operations.clone())
+                    .resources(resources.clone())
+            })
+            .build();
+
+        let policy = PolicyBuilder::from_definition(definition).build().unwrap();
+
+        let usage = policy.memory_usage();
+        assert_eq!(1110, usage.strings);
+        // one entry per identity and operation, and a single set of resources.
+        assert!(
+            usage.static_rules < 1000 * 10 * 16 + 100 * 32 + 64,
+            "{:?}",
+            usage
+        );
+        assert_eq!(0, usage.variable_rules);
+        let request = Request::new("actor_999", "op_9", "resource_99").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+    }
+
+    #[test]
+    fn borrowed_request_test() {
+        let policy = build_policy(
+            r#"{
+                "statements": [
+                    {
+                        "effect": "allow",
+                        "identities": [ "actor_a" ],
+                        "operations": [ "write" ],
+                        "resources": [ "resource_1" ]
+                    }
+                ]
+            }"#,
+        );
+        let buffer = String::from("actor_a write resource_1");
+        let mut values = buffer.split(' ');
+
+        let request = Request::new(
+            values.next().unwrap(),
+            values.next().unwrap(),
+            values.next().unwrap(),
+        )
+        .unwrap();
+
+        assert_matches!(request.identity, Cow::Borrowed(_));
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+        let request: Request<'static, ()> = request.into_owned();
+        drop(buffer);
+        assert_matches!(request.resource, Cow::Owned(_));
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+    }
+
+    #[test]
+    fn policy_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Policy<Default, DefaultSubstituter>>();
+    }
+
+    #[test]
+    fn evaluate_variable_rules_from_matcher() {
+        /// Matches only resources allowed by another policy with variable rules.
+        struct PolicyMatcher(Policy<Default, DefaultSubstituter>);
+
+        impl ResourceMatcher for PolicyMatcher {
+            type Context = ();
+
+            fn do_match(
+                &self,
+                context: &Request<'_, Self::Context>,
+                input: &str,
+                policy: &str,
+            ) -> bool {
+                let request = Request::new(context.identity(), "inner", input).unwrap();
+                input == policy && self.0.evaluate(&request).unwrap() == Decision::Allowed
+            }
+        }
+
+        let inner = build_policy(
+            r#"{
+                "statements": [
+                    {
+                        "effect": "allow",
+                        "identities": [ "{{any}}" ],
+                        "operations": [ "inner" ],
+                        "resources": [ "{{identity}}/a" ]
+                    }
+                ]
+            }"#,
+        );
+        let policy = PolicyBuilder::from_json(
+            r#"{
+                "statements": [
+                    {
+                        "effect": "allow",
+                        "identities": [ "{{identity}}" ],
+                        "operations": [ "write" ],
+                        "resources": [ "{{identity}}/a", "{{identity}}/b" ]
+                    }
+                ]
+            }"#,
+        )
+        .with_matcher(PolicyMatcher(inner))
+        .build()
+        .unwrap();
+
+        let request = Request::new("actor_a", "write", "actor_a/a").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+        let request = Request::new("actor_a", "write", "actor_a/b").unwrap();
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Denied));
+    }
+
     /// See test case above for details.
     #[test]
     fn rule_ordering_should_work_for_custom_matchers_variable_rules() {
-        let json = r###"{
+        let json = r#"{
             "statements": [
                 {
                     "effect": "allow",
@@ -691,7 +1197,7 @@ pub(crate) mod tests {
                     ]
                 }
             ]
-        }"###;
+        }"#;
 
         let policy = PolicyBuilder::from_json(json)
             .with_default_decision(Decision::Denied)
@@ -718,7 +1224,7 @@ pub(crate) mod tests {
     /// This case is created as a result of a discovered bug.
     #[test]
     fn all_identity_variable_rules_must_be_evaluated_resources_do_not_match() {
-        let json = r###"{
+        let json = r#"{
             "statements": [
                 {
                     "effect": "deny",
@@ -745,7 +1251,7 @@ pub(crate) mod tests {
                     ]
                 }
             ]
-        }"###;
+        }"#;
 
         let policy = PolicyBuilder::from_json(json)
             .with_default_decision(Decision::Denied)
@@ -766,7 +1272,7 @@ pub(crate) mod tests {
     /// This case is created as a result of a discovered bug.
     #[test]
     fn all_identity_variable_rules_must_be_evaluated_operations_do_not_match() {
-        let json = r###"{
+        let json = r#"{
             "statements": [
                 {
                     "effect": "deny",
@@ -793,7 +1299,7 @@ pub(crate) mod tests {
                     ]
                 }
             ]
-        }"###;
+        }"#;
 
         let policy = PolicyBuilder::from_json(json)
             .with_default_decision(Decision::Denied)
@@ -807,6 +1313,93 @@ pub(crate) mod tests {
         assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
     }
 
+    #[test]
+    fn evaluate_normalizes_policy_and_request_values() {
+        let json = r#"{
+            "statements": [
+                {
+                    "effect": "allow",
+                    "identities": [
+                        "Actor_A"
+                    ],
+                    "operations": [
+                        "write"
+                    ],
+                    "resources": [
+                        "/home/actor_a/"
+                    ]
+                },
+                {
+                    "effect": "allow",
+                    "identities": [
+                        "{{any}}"
+                    ],
+                    "operations": [
+                        "read"
+                    ],
+                    "resources": [
+                        "/home/{{identity}}/"
+                    ]
+                }
+            ]
+        }"#;
+
+        let policy = PolicyBuilder::from_json(json)
+            .with_default_decision(Decision::Denied)
+            .with_normalizer(
+                normalizer::Pipeline::new()
+                    .identity(vec![normalizer::Step::CaseFold])
+                    .resource(vec![
+                        normalizer::Step::PercentDecode,
+                        normalizer::Step::CaseFold,
+                        normalizer::Step::TrimTrailingSlash,
+                    ]),
+            )
+            .build()
+            .expect("Unable to build policy from json.");
+
+        let request = Request::new("ACTOR_A", "write", "/Home/%61ctor_a//").unwrap();
+
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+
+        let request = Request::new("Actor_B", "read", "/home/actor_b/").unwrap();
+
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Allowed));
+
+        let request = Request::new("actor_a", "WRITE", "/home/actor_a").unwrap();
+
+        assert_matches!(policy.evaluate(&request), Ok(Decision::Denied));
+    }
+
+    #[test_case("/files/%2541", Decision::Allowed; "static rule")]
+    #[test_case("/actor_a/%2541", Decision::Allowed; "variable rule")]
+    #[test_case("/actor_a/A", Decision::Denied; "variable rule decoded twice")]
+    #[test_case("%7B%7Bany%7D%7D", Decision::Allowed; "decoded braces")]
+    #[test_case("other", Decision::Denied; "decoded braces are not a variable")]
+    fn evaluate_normalizes_values_once(resource: &str, expected: Decision) {
+        let json = r#"{
+            "statements": [
+                {
+                    "effect": "allow",
+                    "identities": [ "actor_a" ],
+                    "operations": [ "read" ],
+                    "resources": [ "/files/%2541", "/{{identity}}/%2541", "%7B%7Bany%7D%7D" ]
+                }
+            ]
+        }"#;
+
+        let policy = PolicyBuilder::from_json(json)
+            .with_default_decision(Decision::Denied)
+            .with_normalizer(
+                normalizer::Pipeline::new().resource(vec![normalizer::Step::PercentDecode]),
+            )
+            .build()
+            .unwrap();
+
+        let request = Request::new("actor_a", "read", resource).unwrap();
+        assert_eq!(expected, policy.evaluate(&request).unwrap());
+    }
+
     /// `TestSubstituter` replaces any value with the corresponding identity
     /// from the request, thus making the variable rule to always match the request.
     #[derive(Debug)]
@@ -815,19 +1408,27 @@ pub(crate) mod tests {
     impl Substituter for TestIdentitySubstituter {
         type Context = ();
 
-        fn visit_identity(&self, _value: &str, context: &Request<Self::Context>) -> Result<String> {
-            Ok(context.identity.clone())
+        fn visit_identity(
+            &self,
+            _value: &str,
+            context: &Request<'_, Self::Context>,
+        ) -> Result<String> {
+            Ok(context.identity().to_string())
         }
 
         fn visit_operation(
             &self,
             _value: &str,
-            context: &Request<Self::Context>,
+            context: &Request<'_, Self::Context>,
         ) -> Result<String> {
-            Ok(context.operation.clone())
+            Ok(context.operation().to_string())
         }
 
-        fn visit_resource(&self, value: &str, _context: &Request<Self::Context>) -> Result<String> {
+        fn visit_resource(
+            &self,
+            value: &str,
+            _context: &Request<'_, Self::Context>,
+        ) -> Result<String> {
             Ok(value.into())
         }
     }
@@ -841,13 +1442,15 @@ pub(crate) mod tests {
     impl ResourceMatcher for StartWithMatcher {
         type Context = ();
 
-        fn do_match(&self, _: &Request<Self::Context>, input: &str, policy: &str) -> bool {
+        fn do_match(&self, _: &Request<'_, Self::Context>, input: &str, policy: &str) -> bool {
             policy.starts_with(input)
         }
     }
 
     #[cfg(feature = "proptest")]
     mod proptests {
+        use std::collections::BTreeMap;
+
         use crate::{Decision, Effect, PolicyBuilder, PolicyDefinition, Request, Statement};
         use proptest::{collection::vec, prelude::*};
 
@@ -895,6 +1498,10 @@ pub(crate) mod tests {
                 statements in vec(arb_statement(), 1..5)
             ) -> PolicyDefinition {
                 PolicyDefinition {
+                    schema_version: PolicyDefinition::LATEST_SCHEMA_VERSION,
+                    id: None,
+                    version: None,
+                    metadata: BTreeMap::new(),
                     statements
                 }
             }
@@ -910,11 +1517,15 @@ pub(crate) mod tests {
             ) -> Statement {
                 Statement{
                     order: 0,
+                    sid: None,
                     description,
                     effect,
                     identities,
                     operations,
                     resources,
+                    metadata: BTreeMap::new(),
+                    line: None,
+                    source: None,
                 }
             }
         }
@@ -0,0 +1,324 @@
+use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::TryFrom,
+    mem::size_of,
+    sync::Arc,
+};
+
+use super::{
+    interner::{Interner, Sym, Symbols},
+    trie::PrefixTrie,
+    EffectOrd,
+};
+use crate::{Error, Result};
+
+/// Static rules stored in arenas with interned strings.
+///
+/// Every (identity, operation) pair refers to a set of resources. Pairs that are
+/// granted by the same statements share the set, so the resources of a statement
+/// are not copied for each of its identities and operations. Sets and strings are also
+/// shared with the rules of incrementally updated policies.
+#[derive(Debug, Default)]
+pub(crate) struct StaticRules {
+    symbols: Arc<Symbols>,
+    /// Resource sets of (identity, operation) pairs, sorted for lookups.
+    entries: Box<[(Sym, Sym, Arc<ResourceSet>)]>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ResourceSet {
+    /// Resources sorted by symbol.
+    resources: Box<[(Sym, EffectOrd)]>,
+    index: Option<PrefixTrie>,
+}
+
+impl StaticRules {
+    /// Returns the resources of the identity and operation.
+    pub fn get(&self, identity: &str, operation: &str) -> Option<&ResourceSet> {
+        let key = (self.symbols.get(identity)?, self.symbols.get(operation)?);
+        let i = self
+            .entries
+            .binary_search_by(|(identity, operation, _)| (*identity, *operation).cmp(&key))
+            .ok()?;
+        Some(&self.entries[i].2)
+    }
+
+    /// Returns the resource equal to the input.
+    pub fn find_equal(&self, set: &ResourceSet, input: &str) -> Option<EffectOrd> {
+        let sym = self.symbols.get(input)?;
+        let i = set
+            .resources
+            .binary_search_by_key(&sym, |(resource, _)| *resource)
+            .ok()?;
+        Some(set.resources[i].1)
+    }
+
+    pub fn iter<'a>(
+        &'a self,
+        set: &'a ResourceSet,
+    ) -> impl Iterator<Item = (&'a str, EffectOrd)> + 'a {
+        set.resources
+            .iter()
+            .map(move |(resource, effect)| (self.symbols.resolve(*resource), *effect))
+    }
+
+    /// Returns the number of interned strings and the heap bytes used by them.
+    pub fn strings(&self) -> (usize, usize) {
+        (self.symbols.len(), self.symbols.heap_size())
+    }
+
+    /// Heap bytes used by the rules, excluding strings.
+    pub fn heap_size(&self) -> usize {
+        let mut sets = HashSet::new();
+        self.entries.len() * size_of::<(Sym, Sym, Arc<ResourceSet>)>()
+            + self
+                .entries
+                .iter()
+                .filter(|(_, _, set)| sets.insert(Arc::as_ptr(set)))
+                .map(|(_, _, set)| {
+                    size_of::<ResourceSet>()
+                        + set.resources.len() * size_of::<(Sym, EffectOrd)>()
+                        + set.index.as_ref().map_or(0, PrefixTrie::heap_size)
+                })
+                .sum::<usize>()
+    }
+
+    /// Replaces the rules of the operations with the rules of `update`, which must be built
+    /// by [`StaticRulesBuilder::extend`] from these rules. Other rules are shared.
+    pub fn replace_operations(&self, update: StaticRules, operations: &BTreeSet<String>) -> Self {
+        let mut entries = self
+            .entries
+            .iter()
+            .filter(|(_, operation, _)| !operations.contains(self.symbols.resolve(*operation)))
+            .chain(update.entries.iter().filter(|(_, operation, _)| {
+                operations.contains(update.symbols.resolve(*operation))
+            }))
+            .cloned()
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(identity, operation, _)| (*identity, *operation));
+        Self {
+            symbols: update.symbols,
+            entries: entries.into_boxed_slice(),
+        }
+    }
+}
+
+impl ResourceSet {
+    /// The prefix index, if the resources are indexed.
+    pub fn index(&self) -> Option<&PrefixTrie> {
+        self.index.as_ref()
+    }
+}
+
+/// Collects static rules while a policy is built.
+#[derive(Debug, Default)]
+pub(crate) struct StaticRulesBuilder {
+    interner: Interner,
+    /// The symbols of the rules that are extended.
+    base: Option<Arc<Symbols>>,
+    /// Resources of each statement.
+    statements: Vec<Vec<(Sym, EffectOrd)>>,
+    /// Statements of each (identity, operation) pair.
+    entries: BTreeMap<(Sym, Sym), Vec<u32>>,
+}
+
+impl StaticRulesBuilder {
+    /// Creates a builder that keeps the symbols of the rules, so that its rules can
+    /// replace some of them with [`StaticRules::replace_operations`].
+    pub fn extend(rules: &StaticRules) -> Self {
+        Self {
+            interner: Interner::from_symbols(&rules.symbols),
+            base: Some(Arc::clone(&rules.symbols)),
+            ..Self::default()
+        }
+    }
+
+    /// Adds the static resources of a statement. Returns `None` if there are none.
+    pub fn add_resources<'a>(
+        &mut self,
+        resources: impl IntoIterator<Item = (&'a str, EffectOrd)>,
+    ) -> Result<Option<u32>> {
+        let resources = resources
+            .into_iter()
+            .map(|(resource, effect)| Ok((self.interner.intern(resource)?, effect)))
+            .collect::<Result<Vec<_>>>()?;
+        if resources.is_empty() {
+            return Ok(None);
+        }
+        let id = id(self.statements.len())?;
+        self.statements.push(resources);
+        Ok(Some(id))
+    }
+
+    /// Grants the resources added by [`StaticRulesBuilder::add_resources`] to the pair.
+    pub fn insert(&mut self, identity: &str, operation: &str, resources: u32) -> Result<()> {
+        let key = (
+            self.interner.intern(identity)?,
+            self.interner.intern(operation)?,
+        );
+        let statements = self.entries.entry(key).or_default();
+        if statements.last() != Some(&resources) {
+            statements.push(resources);
+        }
+        Ok(())
+    }
+
+    /// Builds the rules. `index` builds prefix indexes of the resources, see [`PrefixTrie`].
+    ///
+    /// # Errors
+    /// Returns [`Error::PolicyTooLarge`] if the strings of the rules exceed 4 GiB.
+    pub fn build(self, index: Option<PrefixIndex>) -> Result<StaticRules> {
+        let symbols = match self.base {
+            // no strings were added to the extended rules.
+            Some(base) if base.len() == self.interner.len() => base,
+            _ => Arc::new(self.interner.freeze()?),
+        };
+        let mut shared: HashMap<Vec<u32>, Arc<ResourceSet>> = HashMap::new();
+        let mut entries = Vec::with_capacity(self.entries.len());
+        for ((identity, operation), statements) in self.entries {
+            let set = match shared.entry(statements) {
+                Entry::Occupied(entry) => Arc::clone(entry.get()),
+                Entry::Vacant(entry) => {
+                    let set = merge(&self.statements, entry.key(), &symbols, index);
+                    Arc::clone(entry.insert(Arc::new(set)))
+                }
+            };
+            entries.push((identity, operation, set));
+        }
+        Ok(StaticRules {
+            symbols,
+            entries: entries.into_boxed_slice(),
+        })
+    }
+}
+
+/// How resources are indexed for prefix matching.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum PrefixIndex {
+    /// Resources are byte prefixes of the input.
+    Bytes,
+    /// Resources are leading path segments of the input.
+    Paths,
+}
+
+fn id(index: usize) -> Result<u32> {
+    u32::try_from(index)
+        .map_err(|_| Error::PolicyTooLarge(format!("it has more than {} statements", u32::MAX)))
+}
+
+/// Merges the resources of the statements. The lowest order wins for equal resources.
+fn merge(
+    statements: &[Vec<(Sym, EffectOrd)>],
+    ids: &[u32],
+    symbols: &Symbols,
+    index: Option<PrefixIndex>,
+) -> ResourceSet {
+    let mut resources: BTreeMap<Sym, EffectOrd> = BTreeMap::new();
+    for (resource, effect) in ids.iter().flat_map(|id| &statements[*id as usize]) {
+        resources
+            .entry(*resource)
+            .and_modify(|existing| existing.merge(*effect))
+            .or_insert(*effect);
+    }
+    let index = index.map(|index| {
+        let mut trie = PrefixTrie::new(index == PrefixIndex::Paths);
+        for (resource, effect) in &resources {
+            trie.insert(symbols.resolve(*resource), *effect);
+        }
+        trie
+    });
+    ResourceSet {
+        resources: resources.into_iter().collect(),
+        index,
+    }
+}
+
+#[cfg(test)]
+impl StaticRules {
+    /// Returns the identities of all rules.
+    pub fn identities(&self) -> std::collections::BTreeSet<&str> {
+        self.entries
+            .iter()
+            .map(|(identity, _, _)| self.symbols.resolve(*identity))
+            .collect()
+    }
+
+    /// Returns the operations of the identity.
+    pub fn operations(&self, identity: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(i, _, _)| self.symbols.resolve(*i) == identity)
+            .map(|(_, operation, _)| self.symbols.resolve(*operation))
+            .collect()
+    }
+
+    /// Returns the resources of the identity and operation.
+    pub fn resources(&self, identity: &str, operation: &str) -> BTreeMap<&str, EffectOrd> {
+        self.get(identity, operation)
+            .map(|set| self.iter(set).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use crate::Effect;
+
+    use super::*;
+
+    #[test]
+    fn too_many_statements_test() {
+        assert_matches!(id(u32::MAX as usize), Ok(u32::MAX));
+        assert_matches!(id(u32::MAX as usize + 1), Err(Error::PolicyTooLarge(_)));
+    }
+
+    #[test]
+    fn shared_resources_test() {
+        let mut builder = StaticRulesBuilder::default();
+        let first = builder
+            .add_resources([
+                ("a", EffectOrd::new(Effect::Allow, 0)),
+                ("b", EffectOrd::new(Effect::Allow, 0)),
+            ])
+            .unwrap()
+            .unwrap();
+        let second = builder
+            .add_resources([("a", EffectOrd::new(Effect::Deny, 1))])
+            .unwrap()
+            .unwrap();
+        assert_eq!(None, builder.add_resources([]).unwrap());
+        for identity in ["actor_a", "actor_b"] {
+            for operation in ["read", "write"] {
+                builder.insert(identity, operation, first).unwrap();
+            }
+        }
+        builder.insert("actor_a", "read", second).unwrap();
+        builder.insert("actor_c", "read", second).unwrap();
+
+        let rules = builder.build(None).unwrap();
+
+        // {first}, {first, second} and {second}.
+        let sets = rules
+            .entries
+            .iter()
+            .map(|(_, _, set)| Arc::as_ptr(set))
+            .collect::<HashSet<_>>();
+        assert_eq!(3, sets.len());
+        assert_eq!(
+            Some(EffectOrd::new(Effect::Allow, 0)),
+            rules.find_equal(rules.get("actor_a", "read").unwrap(), "a")
+        );
+        assert_eq!(
+            Some(EffectOrd::new(Effect::Deny, 1)),
+            rules.find_equal(rules.get("actor_c", "read").unwrap(), "a")
+        );
+        assert_eq!(
+            None,
+            rules.find_equal(rules.get("actor_c", "read").unwrap(), "b")
+        );
+        assert!(rules.get("actor_c", "write").is_none());
+        assert!(rules.get("actor_d", "read").is_none());
+    }
+}
@@ -0,0 +1,152 @@
+use std::{borrow::Cow, mem::size_of};
+
+use super::EffectOrd;
+use crate::matcher::NormalizedPath;
+
+/// A byte trie of static resources for prefix matching.
+///
+/// Returns the effect with the lowest order among all resources that
+/// the input starts with, in O(length of the input).
+#[derive(Debug, Clone)]
+pub(crate) struct PrefixTrie {
+    nodes: Vec<Node>,
+    /// Resources and inputs are paths matched by segments, as in
+    /// [`PathPrefix`](`crate::matcher::PathPrefix`).
+    paths: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    effect: Option<EffectOrd>,
+    /// Child node indexes, sorted by byte.
+    children: Vec<(u8, usize)>,
+}
+
+impl PrefixTrie {
+    pub fn new(paths: bool) -> Self {
+        Self {
+            nodes: vec![Node::default()],
+            paths,
+        }
+    }
+
+    pub fn insert(&mut self, resource: &str, effect: EffectOrd) {
+        // a path that escapes its root matches nothing.
+        let Some(resource) = self.key(resource) else {
+            return;
+        };
+        let mut node = 0;
+        for byte in resource.bytes() {
+            node = match self.nodes[node]
+                .children
+                .binary_search_by_key(&byte, |(b, _)| *b)
+            {
+                Ok(i) => self.nodes[node].children[i].1,
+                Err(i) => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node::default());
+                    self.nodes[node].children.insert(i, (byte, child));
+                    child
+                }
+            };
+        }
+        match &mut self.nodes[node].effect {
+            Some(existing) => existing.merge(effect),
+            None => self.nodes[node].effect = Some(effect),
+        }
+    }
+
+    pub fn find(&self, input: &str) -> Option<EffectOrd> {
+        let input = self.key(input)?;
+        let mut node = &self.nodes[0];
+        let mut result = node.effect;
+        for byte in input.bytes() {
+            node = match node.children.binary_search_by_key(&byte, |(b, _)| *b) {
+                Ok(i) => &self.nodes[node.children[i].1],
+                Err(_) => break,
+            };
+            if let Some(effect) = node.effect {
+                if result.map_or(true, |r| effect.order < r.order) {
+                    result = Some(effect);
+                }
+            }
+        }
+        result
+    }
+
+    /// Paths are keyed by their normalized segments, each followed by `/`, so that
+    /// a key only is a prefix of another at a segment boundary. Relative paths start
+    /// with `./` to never match absolute ones.
+    fn key<'a>(&self, value: &'a str) -> Option<Cow<'a, str>> {
+        if !self.paths {
+            return Some(Cow::Borrowed(value));
+        }
+        let path = NormalizedPath::new(value)?;
+        let mut key = String::from(if path.is_absolute() { "/" } else { "./" });
+        for segment in path.segments() {
+            key.push_str(segment);
+            key.push('/');
+        }
+        Some(Cow::Owned(key))
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.nodes.capacity() * size_of::<Node>()
+            + self
+                .nodes
+                .iter()
+                .map(|node| node.children.capacity() * size_of::<(u8, usize)>())
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use crate::Effect;
+
+    use super::*;
+
+    #[test_case("/home/alice/notes", Some(1); "lowest order prefix")]
+    #[test_case("/home/bob", Some(2); "single prefix")]
+    #[test_case("/home", None; "shorter than prefixes")]
+    #[test_case("/etc", None; "no prefix")]
+    #[test_case("/home/alice", Some(1); "equal to prefix")]
+    fn find_test(input: &str, expected: Option<usize>) {
+        let mut trie = PrefixTrie::new(false);
+        trie.insert("/home/", EffectOrd::new(Effect::Allow, 2));
+        trie.insert("/home/alice", EffectOrd::new(Effect::Deny, 1));
+        trie.insert("/home/alice/notes", EffectOrd::new(Effect::Allow, 3));
+
+        assert_eq!(expected, trie.find(input).map(|effect| effect.order));
+    }
+
+    #[test]
+    fn empty_prefix_matches_everything_test() {
+        let mut trie = PrefixTrie::new(false);
+        trie.insert("", EffectOrd::new(Effect::Allow, 5));
+        trie.insert("/a", EffectOrd::new(Effect::Deny, 7));
+
+        assert_eq!(Some(5), trie.find("/a/b").map(|effect| effect.order));
+        assert_eq!(Some(5), trie.find("").map(|effect| effect.order));
+    }
+
+    #[test_case("/home/alice/notes", Some(1); "child path")]
+    #[test_case("/home/alice", Some(1); "equal path")]
+    #[test_case("/home/alicia", Some(2); "partial segment")]
+    #[test_case("//home/bob/../alice/./x", Some(1); "normalized input")]
+    #[test_case("/home/alice/../../..", None; "traversal above root")]
+    #[test_case("home/alice", Some(4); "relative input")]
+    #[test_case("/etc", None; "no prefix")]
+    fn find_paths_test(input: &str, expected: Option<usize>) {
+        let mut trie = PrefixTrie::new(true);
+        trie.insert("/home/", EffectOrd::new(Effect::Allow, 2));
+        trie.insert("/home//alice", EffectOrd::new(Effect::Deny, 1));
+        trie.insert("/home/al", EffectOrd::new(Effect::Deny, 0));
+        trie.insert("home", EffectOrd::new(Effect::Allow, 4));
+        trie.insert("/..", EffectOrd::new(Effect::Allow, 0));
+
+        assert_eq!(expected, trie.find(input).map(|effect| effect.order));
+    }
+}
@@ -0,0 +1,784 @@
+use std::{collections::BTreeSet, error::Error as StdError, fmt, sync::Arc};
+
+use super::{
+    builder::{check, compile, normalize_statement, validate, SharedValidator, Validate},
+    static_rules::StaticRulesBuilder,
+    versions, Policy, PolicyDefinition, Statement,
+};
+use crate::{Error, Normalizer, PolicyValidator, ResourceMatcher, Result, Substituter};
+
+/// An incremental update of a [`Policy`], created by [`Policy::update`].
+///
+/// Added statements have the lowest priority, and replaced statements keep their position,
+/// so the order of the other statements is preserved. Only the rules of the operations of
+/// changed statements are rebuilt, other rules are shared with the updated policy.
+/// Use [`Policy::compact`] to release the memory of rules that are no longer used.
+///
+/// ```rust
+/// use allow_me::{Decision, PolicyBuilder, Request, Statement};
+///
+/// let json = r#"{
+///     "schemaVersion": 2,
+///     "statements": [
+///         {
+///             "sid": "deny-actor-a",
+///             "effect": "deny",
+///             "identities": [ "actor_a" ],
+///             "operations": [ "write" ],
+///             "resources": [ "resource_1" ]
+///         }
+///     ]
+/// }"#;
+///
+/// let policy = PolicyBuilder::from_json(json).build_updatable().unwrap();
+/// let policy = policy
+///     .update()
+///     .remove("deny-actor-a")
+///     .append(
+///         Statement::builder()
+///             .sid("allow-actor-a")
+///             .allow()
+///             .identity("actor_a")
+///             .operation("write")
+///             .resource("resource_1")
+///             .build(),
+///     )
+///     .apply()
+///     .unwrap();
+///
+/// let request = Request::new("actor_a", "write", "resource_1").unwrap();
+/// assert_eq!(Decision::Allowed, policy.evaluate(&request).unwrap());
+/// ```
+pub struct PolicyUpdate<'a, R, S, N> {
+    policy: &'a Policy<R, S, N>,
+    validator: Option<Box<Validate<'a>>>,
+    changes: Vec<Change>,
+}
+
+impl<R: fmt::Debug, S: fmt::Debug, N: fmt::Debug> fmt::Debug for PolicyUpdate<'_, R, S, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyUpdate")
+            .field("policy", &self.policy)
+            .field("changes", &self.changes)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+enum Change {
+    Append(Statement),
+    Remove(String),
+    Replace(String, Statement),
+}
+
+impl<R, S, N> Policy<R, S, N> {
+    /// Starts an incremental update of the policy. The policy itself is not changed.
+    ///
+    /// The updated statements are validated with the [`PolicyValidator`] the policy was
+    /// built with, if it was kept by [`PolicyBuilder::build_updatable`](`crate::PolicyBuilder::build_updatable`),
+    /// unless another validator is specified with [`PolicyUpdate::with_validator`].
+    pub fn update(&self) -> PolicyUpdate<'_, R, S, N> {
+        PolicyUpdate {
+            policy: self,
+            validator: None,
+            changes: vec![],
+        }
+    }
+}
+
+impl<'a, R, S, N> PolicyUpdate<'a, R, S, N> {
+    /// Adds the statement after all other statements.
+    #[must_use]
+    pub fn append(mut self, statement: Statement) -> Self {
+        self.changes.push(Change::Append(statement));
+        self
+    }
+
+    /// Removes the statement with the id.
+    #[must_use]
+    pub fn remove(mut self, sid: impl Into<String>) -> Self {
+        self.changes.push(Change::Remove(sid.into()));
+        self
+    }
+
+    /// Replaces the statement with the id, keeping its position.
+    #[must_use]
+    pub fn replace(mut self, sid: impl Into<String>, statement: Statement) -> Self {
+        self.changes.push(Change::Replace(sid.into(), statement));
+        self
+    }
+
+    /// Specifies the [`PolicyValidator`] to validate the updated statements. The updated
+    /// policy keeps the validator of the policy, if any, for later updates.
+    #[must_use]
+    pub fn with_validator<V, E>(mut self, validator: V) -> Self
+    where
+        V: PolicyValidator<Error = E> + 'a,
+        E: StdError + Sync + Into<Box<dyn StdError>> + 'static,
+    {
+        self.validator = Some(Box::new(move |definition: &PolicyDefinition| {
+            validate(&validator, definition)
+        }));
+        self
+    }
+}
+
+impl<R, S, N> PolicyUpdate<'_, R, S, N>
+where
+    R: ResourceMatcher,
+    S: Substituter,
+    N: Normalizer,
+{
+    /// Applies the changes in order and returns the updated policy.
+    ///
+    /// # Errors
+    /// Returns [`Error::MissingValidator`] if the policy does not keep a validator and none
+    /// is specified, [`Error::StatementNotFound`] if a removed or replaced statement does
+    /// not exist, and [`Error::PolicyTooLarge`] if the rules exceed their size limits.
+    ///
+    /// Returns [`Error::Validation`] if the statements fail the validator, an updated
+    /// statement id is empty or already used, a variable has unbalanced braces, or a
+    /// variable is unknown and the policy was built with [`Severity::Fail`](`crate::validator::Severity::Fail`)
+    /// for unknown variables.
+    pub fn apply(self) -> Result<Policy<R, S, N>> {
+        let PolicyUpdate {
+            policy,
+            validator,
+            changes,
+        } = self;
+        let validator = match &validator {
+            Some(validator) => &**validator,
+            None => policy
+                .validator
+                .as_ref()
+                .ok_or(Error::MissingValidator)?
+                .as_validate(),
+        };
+
+        let mut statements = policy.statements.clone();
+        let mut changed = BTreeSet::new();
+        for change in changes {
+            let (order, statement) = match change {
+                Change::Append(statement) => {
+                    statements.push(None);
+                    (statements.len() - 1, Some(statement))
+                }
+                Change::Remove(sid) => (position(&statements, &sid)?, None),
+                Change::Replace(sid, statement) => (position(&statements, &sid)?, Some(statement)),
+            };
+            statements[order] = statement.map(|mut statement| {
+                statement.order = order;
+                normalize_statement(&mut statement, &*policy.normalizer);
+                Arc::new(statement)
+            });
+            changed.insert(order);
+        }
+
+        // the validator may check statements against each other, so it gets all of them.
+        let warnings = check(
+            &policy.definition(statements.iter().flatten()),
+            validator,
+            &changed,
+            &*policy.substituter,
+            &policy.filters,
+            policy.unknown_variables,
+        )?;
+
+        // the rules of operations of the removed and new statements are rebuilt.
+        let mut operations = BTreeSet::new();
+        for order in &changed {
+            if let Some(statement) = policy.statements.get(*order).and_then(Option::as_ref) {
+                operations.extend(statement.operations.iter().cloned());
+            }
+            if let Some(statement) = &statements[*order] {
+                operations.extend(statement.operations.iter().cloned());
+            }
+        }
+
+        let (static_rules, variable_rules) = compile(
+            statements
+                .iter()
+                .flatten()
+                .map(AsRef::as_ref)
+                .filter(|statement| {
+                    statement
+                        .operations
+                        .iter()
+                        .any(|operation| operations.contains(operation))
+                }),
+            StaticRulesBuilder::extend(&policy.static_rules),
+            &*policy.resource_matcher,
+            &*policy.substituter,
+            &*policy.normalizer,
+            &policy.filters,
+        )?;
+
+        Ok(Policy {
+            id: policy.id.clone(),
+            version: policy.version.clone(),
+            metadata: policy.metadata.clone(),
+            statements,
+            warnings,
+            default_decision: policy.default_decision,
+            unknown_variables: policy.unknown_variables,
+            validator: policy.validator.clone(),
+            resource_matcher: Arc::clone(&policy.resource_matcher),
+            substituter: Arc::clone(&policy.substituter),
+            normalizer: Arc::clone(&policy.normalizer),
+            filters: Arc::clone(&policy.filters),
+            static_rules: policy
+                .static_rules
+                .replace_operations(static_rules, &operations),
+            variable_rules: policy
+                .variable_rules
+                .replace_operations(variable_rules, &operations),
+        })
+    }
+}
+
+impl<R, S, N> Policy<R, S, N>
+where
+    R: ResourceMatcher,
+    S: Substituter,
+    N: Normalizer,
+{
+    /// Rebuilds the policy from its statements.
+    ///
+    /// An updated policy keeps the strings of the rules it was updated from, and removed
+    /// statements leave empty slots, so memory grows with the number of updates, see
+    /// [`Policy::memory_usage`]. Compacting drops them. Statements keep their priority,
+    /// but are renumbered without gaps.
+    ///
+    /// # Errors
+    /// Returns [`Error::PolicyTooLarge`] if the rules exceed their size limits.
+    pub fn compact(&self) -> Result<Self> {
+        let statements = self
+            .statements
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(order, statement)| {
+                if statement.order == order {
+                    Arc::clone(statement)
+                } else {
+                    let mut statement = (**statement).clone();
+                    statement.order = order;
+                    Arc::new(statement)
+                }
+            })
+            .collect::<Vec<_>>();
+        let no_validator: &Validate<'_> = &|_| Ok(vec![]);
+        let warnings = check(
+            &self.definition(&statements),
+            self.validator
+                .as_ref()
+                .map_or(no_validator, SharedValidator::as_validate),
+            &BTreeSet::new(),
+            &*self.substituter,
+            &self.filters,
+            self.unknown_variables,
+        )?;
+        let (static_rules, variable_rules) = compile(
+            statements.iter().map(AsRef::as_ref),
+            StaticRulesBuilder::default(),
+            &*self.resource_matcher,
+            &*self.substituter,
+            &*self.normalizer,
+            &self.filters,
+        )?;
+
+        Ok(Policy {
+            id: self.id.clone(),
+            version: self.version.clone(),
+            metadata: self.metadata.clone(),
+            statements: statements.into_iter().map(Some).collect(),
+            warnings,
+            default_decision: self.default_decision,
+            unknown_variables: self.unknown_variables,
+            validator: self.validator.clone(),
+            resource_matcher: Arc::clone(&self.resource_matcher),
+            substituter: Arc::clone(&self.substituter),
+            normalizer: Arc::clone(&self.normalizer),
+            filters: Arc::clone(&self.filters),
+            static_rules,
+            variable_rules,
+        })
+    }
+}
+
+impl<R, S, N> Policy<R, S, N> {
+    /// Returns the definition of the policy with the statements.
+    fn definition<'a>(
+        &self,
+        statements: impl IntoIterator<Item = &'a Arc<Statement>>,
+    ) -> PolicyDefinition {
+        PolicyDefinition {
+            schema_version: versions::LATEST,
+            id: self.id.clone(),
+            version: self.version.clone(),
+            metadata: self.metadata.clone(),
+            statements: statements
+                .into_iter()
+                .map(|statement| (**statement).clone())
+                .collect(),
+        }
+    }
+}
+
+/// Returns the order of the statement with the id.
+fn position(statements: &[Option<Arc<Statement>>], sid: &str) -> Result<usize> {
+    statements
+        .iter()
+        .position(|statement| {
+            statement
+                .as_ref()
+                .is_some_and(|statement| statement.sid() == Some(sid))
+        })
+        .ok_or_else(|| Error::StatementNotFound(sid.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use assert_matches::assert_matches;
+
+    use crate::{
+        filter,
+        matcher::{MatchSemantics, StartsWith},
+        validator::Severity,
+        Decision, DefaultSubstituter, DefaultValidator, PolicyAnalyzer, PolicyBuilder, Request,
+        Statement,
+    };
+
+    use super::*;
+
+    const POLICY: &str = r#"{
+        "schemaVersion": 2,
+        "statements": [
+            {
+                "sid": "deny-secrets",
+                "effect": "deny",
+                "identities": [ "actor_a" ],
+                "operations": [ "read" ],
+                "resources": [ "files/secrets" ]
+            },
+            {
+                "sid": "allow-files",
+                "effect": "allow",
+                "identities": [ "actor_a" ],
+                "operations": [ "read" ],
+                "resources": [ "files/" ]
+            },
+            {
+                "sid": "allow-own-events",
+                "effect": "allow",
+                "identities": [ "{{any}}" ],
+                "operations": [ "publish" ],
+                "resources": [ "events/{{identity}}" ]
+            },
+            {
+                "sid": "allow-metrics",
+                "effect": "allow",
+                "identities": [ "actor_a" ],
+                "operations": [ "write" ],
+                "resources": [ "metrics" ]
+            }
+        ]
+    }"#;
+
+    fn build() -> Policy<StartsWith, DefaultSubstituter> {
+        PolicyBuilder::from_json(POLICY)
+            .with_matcher(StartsWith)
+            .build_updatable()
+            .unwrap()
+    }
+
+    fn evaluate(
+        policy: &Policy<StartsWith, DefaultSubstituter>,
+        operation: &str,
+        resource: &str,
+    ) -> Decision {
+        let request = Request::new("actor_a", operation, resource).unwrap();
+        policy.evaluate(&request).unwrap()
+    }
+
+    fn statement(sid: &str, operation: &str, resource: &str) -> Statement {
+        Statement::builder()
+            .sid(sid)
+            .allow()
+            .identity("actor_a")
+            .operation(operation)
+            .resource(resource)
+            .build()
+    }
+
+    #[test]
+    fn replace_keeps_order_test() {
+        let policy = build();
+
+        let updated = policy
+            .update()
+            .replace("allow-files", statement("allow-files", "read", "files/"))
+            .replace(
+                "deny-secrets",
+                Statement::builder()
+                    .sid("deny-secrets")
+                    .deny()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource("files/private")
+                    .build(),
+            )
+            .apply()
+            .unwrap();
+
+        assert_eq!(
+            Decision::Denied,
+            evaluate(&updated, "read", "files/private")
+        );
+        assert_eq!(
+            Decision::Allowed,
+            evaluate(&updated, "read", "files/secrets")
+        );
+        // the original policy is not changed.
+        assert_eq!(Decision::Denied, evaluate(&policy, "read", "files/secrets"));
+    }
+
+    #[test]
+    fn append_has_lowest_priority_test() {
+        let policy = build();
+
+        let updated = policy
+            .update()
+            .append(statement("allow-secrets", "read", "files/secrets"))
+            .append(statement("allow-logs", "read", "logs/"))
+            .apply()
+            .unwrap();
+
+        assert_eq!(
+            Decision::Denied,
+            evaluate(&updated, "read", "files/secrets")
+        );
+        assert_eq!(Decision::Allowed, evaluate(&updated, "read", "logs/today"));
+        assert_eq!(Some("allow-logs"), updated.sid(5));
+    }
+
+    #[test]
+    fn remove_test() {
+        let policy = build();
+
+        let updated = policy
+            .update()
+            .remove("deny-secrets")
+            .remove("allow-own-events")
+            .apply()
+            .unwrap();
+
+        assert_eq!(
+            Decision::Allowed,
+            evaluate(&updated, "read", "files/secrets")
+        );
+        assert_eq!(
+            Decision::Denied,
+            evaluate(&updated, "publish", "events/actor_a")
+        );
+        assert_eq!(
+            Decision::Allowed,
+            evaluate(&policy, "publish", "events/actor_a")
+        );
+        assert_eq!(None, updated.sid(0));
+        assert_eq!(Some("allow-files"), updated.sid(1));
+    }
+
+    #[test]
+    fn shares_unchanged_rules_test() {
+        let policy = build();
+
+        let updated = policy
+            .update()
+            .replace("allow-files", statement("allow-files", "read", "docs/"))
+            .apply()
+            .unwrap();
+
+        let old = policy.static_rules.get("actor_a", "write").unwrap();
+        let new = updated.static_rules.get("actor_a", "write").unwrap();
+        assert!(std::ptr::eq(old, new));
+        assert_eq!(
+            vec!["docs/", "files/secrets"],
+            updated
+                .static_rules
+                .resources("actor_a", "read")
+                .keys()
+                .copied()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(Arc::as_ptr(&policy.resource_matcher)),
+            Some(Arc::as_ptr(&updated.resource_matcher))
+        );
+        assert_eq!(
+            Decision::Allowed,
+            evaluate(&updated, "publish", "events/actor_a")
+        );
+    }
+
+    #[test]
+    fn update_variable_rules_test() {
+        let policy = build();
+
+        let updated = policy
+            .update()
+            .replace(
+                "allow-own-events",
+                Statement::builder()
+                    .sid("allow-own-events")
+                    .allow()
+                    .identity("{{any}}")
+                    .operation("publish")
+                    .resource("alerts/{{identity}}")
+                    .build(),
+            )
+            .apply()
+            .unwrap();
+
+        assert_eq!(
+            Decision::Denied,
+            evaluate(&updated, "publish", "events/actor_a")
+        );
+        assert_eq!(
+            Decision::Allowed,
+            evaluate(&updated, "publish", "alerts/actor_a")
+        );
+    }
+
+    #[test]
+    fn matches_full_rebuild_test() {
+        let updated = build()
+            .update()
+            .remove("deny-secrets")
+            .replace(
+                "allow-metrics",
+                statement("allow-metrics", "read", "files/secrets"),
+            )
+            .append(statement("allow-write", "write", "metrics"))
+            .apply()
+            .unwrap();
+
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource("files/")
+            })
+            .statement(|s| {
+                s.allow()
+                    .identity("{{any}}")
+                    .operation("publish")
+                    .resource("events/{{identity}}")
+            })
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource("files/secrets")
+            })
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("write")
+                    .resource("metrics")
+            })
+            .build();
+        let rebuilt = PolicyBuilder::from_definition(definition)
+            .with_matcher(StartsWith)
+            .build()
+            .unwrap();
+
+        for (operation, resource) in [
+            ("read", "files/secrets"),
+            ("read", "files/a"),
+            ("write", "metrics"),
+            ("publish", "events/actor_a"),
+            ("publish", "events/actor_b"),
+        ] {
+            assert_eq!(
+                evaluate(&rebuilt, operation, resource),
+                evaluate(&updated, operation, resource),
+                "{operation} {resource}"
+            );
+        }
+    }
+
+    #[test]
+    fn custom_filters_test() {
+        let policy = PolicyBuilder::from_json(POLICY)
+            .with_matcher(StartsWith)
+            .with_filter("reverse", |value, _: Option<&str>| {
+                Ok(match value {
+                    filter::Value::Str(s) => filter::Value::Str(s.chars().rev().collect()),
+                    value => value,
+                })
+            })
+            .build_updatable()
+            .unwrap();
+
+        let updated = policy
+            .update()
+            .append(statement("reverse", "read", "reverse/{{identity|reverse}}"))
+            .apply()
+            .unwrap();
+
+        assert!(updated.warnings().is_empty());
+        assert_eq!(
+            Decision::Allowed,
+            evaluate(&updated, "read", "reverse/a_rotca")
+        );
+    }
+
+    #[test]
+    fn compact_test() {
+        let mut policy = build();
+        for i in 0..10 {
+            policy = policy
+                .update()
+                .replace(
+                    "allow-files",
+                    statement("allow-files", "read", &format!("files/{i}")),
+                )
+                .remove("allow-metrics")
+                .append(statement("allow-metrics", "write", &format!("metrics/{i}")))
+                .apply()
+                .unwrap();
+        }
+        let updated = policy.memory_usage();
+
+        let compacted = policy.compact().unwrap();
+
+        assert!(compacted.memory_usage().strings < updated.strings);
+        assert!(compacted.memory_usage().statements < updated.statements);
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            compacted
+                .statements
+                .iter()
+                .flatten()
+                .map(|statement| statement.order)
+                .collect::<Vec<_>>()
+        );
+        for (operation, resource) in [
+            ("read", "files/9"),
+            ("read", "files/8"),
+            ("read", "files/secrets"),
+            ("write", "metrics/9"),
+            ("write", "metrics/8"),
+        ] {
+            assert_eq!(
+                evaluate(&policy, operation, resource),
+                evaluate(&compacted, operation, resource),
+                "{operation} {resource}"
+            );
+        }
+    }
+
+    #[test]
+    fn policy_validator_test() {
+        let policy = PolicyBuilder::from_json(POLICY)
+            .with_matcher(StartsWith)
+            .with_validator(
+                PolicyAnalyzer::new(MatchSemantics::Equality).with_unreachable(Severity::Fail),
+            )
+            .build_updatable()
+            .unwrap();
+
+        // the new statement is shadowed by an unchanged one.
+        assert_matches!(
+            policy
+                .update()
+                .append(statement("shadowed", "read", "files/"))
+                .apply(),
+            Err(Error::Validation(_))
+        );
+        assert_matches!(
+            policy
+                .update()
+                .with_validator(DefaultValidator)
+                .append(statement("shadowed", "read", "files/"))
+                .apply(),
+            Ok(_)
+        );
+    }
+
+    #[test]
+    fn errors_test() {
+        let policy = build();
+
+        assert_matches!(
+            policy.update().remove("missing").apply(),
+            Err(Error::StatementNotFound(sid)) if sid == "missing"
+        );
+        assert_matches!(
+            policy
+                .update()
+                .append(statement("allow-files", "read", "logs/"))
+                .apply(),
+            Err(Error::Validation(_))
+        );
+        assert_matches!(
+            policy
+                .update()
+                .append(Statement::builder().sid("empty").allow().build())
+                .apply(),
+            Err(Error::Validation(_))
+        );
+        assert_matches!(
+            policy
+                .update()
+                .append(statement("unknown", "read", "{{idenity}}"))
+                .apply(),
+            Ok(updated) if updated.warnings().len() == 1
+        );
+        // a replaced statement can not take the id of a later statement.
+        assert_matches!(
+            policy
+                .update()
+                .replace("deny-secrets", statement("allow-metrics", "read", "logs/"))
+                .apply(),
+            Err(Error::Validation(e)) if e.to_string().contains("already used by statement #3")
+        );
+        // a removed id can be reused.
+        assert_matches!(
+            policy
+                .update()
+                .remove("allow-files")
+                .append(statement("allow-files", "read", "logs/"))
+                .apply(),
+            Ok(_)
+        );
+    }
+
+    #[test]
+    fn missing_validator_test() {
+        let policy = PolicyBuilder::from_json(POLICY)
+            .with_matcher(StartsWith)
+            .build()
+            .unwrap();
+
+        assert_matches!(
+            policy
+                .update()
+                .append(statement("new", "read", "logs/"))
+                .apply(),
+            Err(Error::MissingValidator)
+        );
+        assert_matches!(
+            policy
+                .update()
+                .with_validator(DefaultValidator)
+                .append(statement("new", "read", "logs/"))
+                .apply(),
+            Ok(_)
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    mem::size_of,
+    sync::Arc,
+};
+
+use super::{EffectOrd, Identities};
+use crate::{errors::Result, filter::Filters, Field, Normalizer, Segment, Substituter, Template};
+
+/// Variable rules with identities and resources compiled into templates.
+///
+/// Rules are indexed by operation and by the literal prefix of the identity pattern,
+/// e.g. `tenant-{{x}}` is only considered for identities that start with `tenant-`.
+/// The index assumes that normalization of a substituted identity keeps the literal
+/// prefix and suffix of the normalized pattern.
+///
+/// The rules of an operation are shared with incrementally updated policies.
+#[derive(Debug, Default)]
+pub(crate) struct VariableRules {
+    operations: BTreeMap<String, Arc<Prefixes>>,
+}
+
+/// Rules of an operation by the literal prefix of their identities.
+type Prefixes = BTreeMap<String, Vec<VariableRule>>;
+
+#[derive(Debug)]
+pub(crate) struct VariableRule {
+    /// The identity template, shared by the rules of all its operations.
+    pub identity: Arc<Template>,
+    pub resources: BTreeMap<Template, EffectOrd>,
+    suffix: String,
+    /// The lowest order of the resources.
+    pub order: usize,
+    /// Whether rendering the rule may depend on anything besides the request,
+    /// see [`Substituter::is_volatile`].
+    pub volatile: bool,
+}
+
+impl VariableRules {
+    /// Compiles the variable rules tree produced by [`PolicyBuilder`](`crate::PolicyBuilder`).
+    pub fn compile(
+        rules: Identities,
+        substituter: &impl Substituter,
+        normalizer: &impl Normalizer,
+        filters: &Arc<Filters>,
+    ) -> Result<Self> {
+        let mut result: BTreeMap<String, Prefixes> = BTreeMap::new();
+        for (identity, operations) in rules.0 {
+            // the pattern is normalized after it is rendered. Normalizing it with the variables
+            // may break them, then the rule is considered for every identity.
+            let (prefix, suffix) = Template::parse(&normalizer.normalize_identity(&identity))
+                .map_or_else(
+                    |_| (String::new(), String::new()),
+                    |normalized| {
+                        let (prefix, suffix) = literals(&normalized);
+                        (prefix.to_string(), suffix.to_string())
+                    },
+                );
+            let identity = Arc::new(Template::parse(&identity)?.with_filters(filters));
+            for (operation, resources) in operations.0 {
+                let resources = resources
+                    .0
+                    .into_iter()
+                    .map(|(resource, effect)| {
+                        Ok((Template::parse(&resource)?.with_filters(filters), effect))
+                    })
+                    .collect::<Result<BTreeMap<_, _>>>()?;
+                let Some(order) = resources.values().map(|effect| effect.order).min() else {
+                    continue;
+                };
+                let volatile = substituter.is_volatile(Field::Identities, &identity)
+                    || resources
+                        .keys()
+                        .any(|resource| substituter.is_volatile(Field::Resources, resource));
+                result
+                    .entry(operation)
+                    .or_default()
+                    .entry(prefix.clone())
+                    .or_default()
+                    .push(VariableRule {
+                        identity: Arc::clone(&identity),
+                        resources,
+                        suffix: suffix.clone(),
+                        order,
+                        volatile,
+                    });
+            }
+        }
+        Ok(Self {
+            operations: result
+                .into_iter()
+                .map(|(operation, prefixes)| (operation, Arc::new(prefixes)))
+                .collect(),
+        })
+    }
+
+    /// Replaces the rules of the operations with the rules of `update`. Other rules are shared.
+    pub fn replace_operations(&self, update: VariableRules, operations: &BTreeSet<String>) -> Self {
+        let mut result = self.operations.clone();
+        result.retain(|operation, _| !operations.contains(operation));
+        result.extend(
+            update
+                .operations
+                .into_iter()
+                .filter(|(operation, _)| operations.contains(operation)),
+        );
+        Self { operations: result }
+    }
+
+    /// Returns the rules that may match the normalized identity and operation.
+    pub fn candidates<'a>(
+        &'a self,
+        identity: &'a str,
+        operation: &str,
+    ) -> impl Iterator<Item = &'a VariableRule> + 'a {
+        self.operations
+            .get(operation)
+            .into_iter()
+            .flat_map(move |prefixes| {
+                identity
+                    .char_indices()
+                    .map(|(i, _)| i)
+                    .chain(Some(identity.len()))
+                    .filter_map(move |i| prefixes.get(&identity[..i]))
+                    .flatten()
+            })
+            .filter(move |rule| identity.ends_with(&rule.suffix))
+    }
+
+    /// Heap bytes used by the rules, including strings.
+    pub fn heap_size(&self) -> usize {
+        let mut identities = HashSet::new();
+        let mut result = 0;
+        for (operation, prefixes) in &self.operations {
+            result += operation.capacity();
+            for (prefix, rules) in prefixes.iter() {
+                result += prefix.capacity() + rules.capacity() * size_of::<VariableRule>();
+                for rule in rules {
+                    if identities.insert(Arc::as_ptr(&rule.identity)) {
+                        result += size_of::<Template>() + rule.identity.heap_size();
+                    }
+                    result += rule.suffix.capacity()
+                        + rule
+                            .resources
+                            .keys()
+                            .map(|resource| {
+                                size_of::<(Template, EffectOrd)>() + resource.heap_size()
+                            })
+                            .sum::<usize>();
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Returns the literal prefix and suffix of the template.
+fn literals(template: &Template) -> (&str, &str) {
+    match template.segments() {
+        [Segment::Literal(literal)] => (literal, ""),
+        [first, .., last] => (literal(first), literal(last)),
+        _ => ("", ""),
+    }
+}
+
+fn literal(segment: &Segment) -> &str {
+    match segment {
+        Segment::Literal(literal) => literal,
+        Segment::Variable(_) => "",
+    }
+}
+
+#[cfg(test)]
+impl VariableRules {
+    /// Returns the identity patterns of all rules.
+    pub fn identities(&self) -> std::collections::BTreeSet<&str> {
+        self.rules()
+            .map(|(_, rule)| rule.identity.as_str())
+            .collect()
+    }
+
+    /// Returns the operations of the identity pattern.
+    pub fn operations(&self, identity: &str) -> Vec<&str> {
+        self.rules()
+            .filter(|(_, rule)| rule.identity.as_str() == identity)
+            .map(|(operation, _)| operation)
+            .collect()
+    }
+
+    /// Returns the resources of the identity pattern and operation.
+    pub fn resources(&self, identity: &str, operation: &str) -> &BTreeMap<Template, EffectOrd> {
+        self.rules()
+            .find(|(op, rule)| *op == operation && rule.identity.as_str() == identity)
+            .map(|(_, rule)| &rule.resources)
+            .unwrap()
+    }
+
+    fn rules(&self) -> impl Iterator<Item = (&str, &VariableRule)> {
+        self.operations.iter().flat_map(|(operation, prefixes)| {
+            prefixes
+                .values()
+                .flatten()
+                .map(move |rule| (operation.as_str(), rule))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("{{identity}}", "", ""; "variable")]
+    #[test_case("actor_a", "actor_a", ""; "literal")]
+    #[test_case("tenant-{{identity}}", "tenant-", ""; "prefix")]
+    #[test_case("{{identity}}@corp", "", "@corp"; "suffix")]
+    #[test_case("a-{{identity}}-{{any}}-b", "a-", "-b"; "prefix and suffix")]
+    fn literals_test(identity: &str, prefix: &str, suffix: &str) {
+        assert_eq!(
+            (prefix, suffix),
+            literals(&Template::parse(identity).unwrap())
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Policy definition schema versions.
+//!
+//! Every supported schema version has its own deserializer. Documents in older
+//! versions are migrated to the current [`PolicyDefinition`] model:
+//!
+//! * Version 1 is the original, unversioned format. Unknown fields are ignored.
+//! * Version 2 declares `"schemaVersion": 2` and rejects unknown fields. It adds statement
+//!   ids and metadata, and the policy id, version and metadata.
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{Error, PolicyDefinition, Result};
+
+/// The latest supported schema version of the policy definition.
+pub(super) const LATEST: u32 = 2;
+
+/// A document format that policy definitions can be parsed from.
+pub(super) trait Format {
+    fn deserialize<T: DeserializeOwned>(&self, text: &str) -> Result<T>;
+}
+
+pub(super) struct Json;
+
+impl Format for Json {
+    fn deserialize<T: DeserializeOwned>(&self, text: &str) -> Result<T> {
+        serde_json::from_str(text).map_err(Error::Deserializing)
+    }
+}
+
+#[cfg(feature = "yaml")]
+pub(super) struct Yaml;
+
+#[cfg(feature = "yaml")]
+impl Format for Yaml {
+    fn deserialize<T: DeserializeOwned>(&self, text: &str) -> Result<T> {
+        serde_yaml::from_str(text).map_err(|source| {
+            let location = source.location();
+            Error::DeserializingYaml {
+                line: location.as_ref().map(serde_yaml::Location::line),
+                column: location.as_ref().map(serde_yaml::Location::column),
+                source,
+            }
+        })
+    }
+}
+
+#[cfg(feature = "toml")]
+pub(super) struct Toml;
+
+#[cfg(feature = "toml")]
+impl Format for Toml {
+    fn deserialize<T: DeserializeOwned>(&self, text: &str) -> Result<T> {
+        toml::from_str(text).map_err(|source| {
+            let location = source.span().map(|span| line_column(text, span.start));
+            Error::DeserializingToml {
+                line: location.map(|(line, _)| line),
+                column: location.map(|(_, column)| column),
+                source: Box::new(source),
+            }
+        })
+    }
+}
+
+/// Converts a byte offset in the text into a 1-based line and column.
+pub(super) fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let prefix = &text[..offset.min(text.len())];
+    let line = prefix.matches('\n').count() + 1;
+    let column = prefix.len() - prefix.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/// The schema version of documents that do not declare it.
+pub(super) const UNVERSIONED: u32 = 1;
+
+/// Reads only the schema version of a document, ignoring everything else.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionProbe {
+    #[serde(default)]
+    schema_version: Option<u32>,
+}
+
+/// Parses the document with the deserializer of its schema version
+/// and migrates it to the current model.
+pub(super) fn parse(text: &str, format: &impl Format) -> Result<PolicyDefinition> {
+    let probe: VersionProbe = format.deserialize(text)?;
+    match probe.schema_version.unwrap_or(UNVERSIONED) {
+        UNVERSIONED => format
+            .deserialize::<v1::PolicyDefinition>(text)
+            .map(Into::into),
+        LATEST => format.deserialize(text),
+        version => Err(Error::UnsupportedSchemaVersion(version)),
+    }
+}
+
+/// Checks that the schema version is supported.
+pub(super) fn check(version: u32) -> Result<()> {
+    if (1..=LATEST).contains(&version) {
+        Ok(())
+    } else {
+        Err(Error::UnsupportedSchemaVersion(version))
+    }
+}
+
+mod v1 {
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use crate::{core::definition, Effect};
+
+    #[derive(Deserialize)]
+    pub(super) struct PolicyDefinition {
+        statements: Vec<Statement>,
+    }
+
+    #[derive(Deserialize)]
+    struct Statement {
+        #[serde(default)]
+        description: String,
+        effect: Effect,
+        identities: Vec<String>,
+        operations: Vec<String>,
+        #[serde(default)]
+        resources: Vec<String>,
+    }
+
+    impl From<PolicyDefinition> for definition::PolicyDefinition {
+        fn from(definition: PolicyDefinition) -> Self {
+            Self {
+                schema_version: super::LATEST,
+                id: None,
+                version: None,
+                metadata: BTreeMap::new(),
+                statements: definition.statements.into_iter().map(Into::into).collect(),
+            }
+        }
+    }
+
+    impl From<Statement> for definition::Statement {
+        fn from(statement: Statement) -> Self {
+            Self {
+                order: 0,
+                sid: None,
+                description: statement.description,
+                effect: statement.effect,
+                identities: statement.identities,
+                operations: statement.operations,
+                resources: statement.resources,
+                metadata: BTreeMap::new(),
+                line: None,
+                source: None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    #[test]
+    fn unversioned_document_is_migrated() {
+        let json = r#"{
+            "statements": [
+                {
+                    "sid": "write",
+                    "effect": "allow",
+                    "identities": [ "actor_a" ],
+                    "operations": [ "write" ],
+                    "unknown": "ignored"
+                }
+            ]
+        }"#;
+
+        let definition = parse(json, &Json).unwrap();
+
+        assert_eq!(LATEST, definition.schema_version());
+        assert_eq!(vec!["actor_a"], *definition.statements()[0].identities());
+        // statement ids were introduced in version 2.
+        assert_eq!(None, definition.statements()[0].sid());
+    }
+
+    #[test]
+    fn definition_without_version_is_unversioned() {
+        let definition: PolicyDefinition =
+            serde_json::from_str(r#"{ "statements": [ ] }"#).unwrap();
+
+        assert_eq!(UNVERSIONED, definition.schema_version());
+    }
+
+    #[test]
+    fn latest_version_rejects_unknown_fields() {
+        let json = r#"{
+            "schemaVersion": 2,
+            "statements": [
+                {
+                    "effect": "allow",
+                    "identites": [ "actor_a" ],
+                    "operations": [ "write" ]
+                }
+            ]
+        }"#;
+
+        assert_matches!(parse(json, &Json), Err(Error::Deserializing(_)));
+    }
+
+    #[test]
+    fn future_version_is_rejected() {
+        let json = r#"{
+            "schemaVersion": 3,
+            "statements": [ ]
+        }"#;
+
+        let error = parse(json, &Json).unwrap_err();
+
+        assert_matches!(error, Error::UnsupportedSchemaVersion(3));
+        assert_eq!(
+            "Policy definition schema version 3 is not supported. The latest supported version is 2.",
+            error.to_string()
+        );
+    }
+}
@@ -10,9 +10,127 @@ pub enum Error {
     #[error("An error occurred deserializing policy definition: {0}.")]
     Deserializing(#[source] serde_json::Error),
 
+    #[cfg(feature = "yaml")]
+    #[error("An error occurred deserializing yaml policy definition: {source}.")]
+    DeserializingYaml {
+        line: Option<usize>,
+        column: Option<usize>,
+        #[source]
+        source: serde_yaml::Error,
+    },
+
+    #[cfg(feature = "toml")]
+    #[error("An error occurred deserializing toml policy definition: {source}.")]
+    DeserializingToml {
+        line: Option<usize>,
+        column: Option<usize>,
+        #[source]
+        source: Box<toml::de::Error>,
+    },
+
+    #[error(
+        "Policy definition schema version {0} is not supported. The latest supported version is {}.",
+        crate::PolicyDefinition::LATEST_SCHEMA_VERSION
+    )]
+    UnsupportedSchemaVersion(u32),
+
+    #[error("An error occurred serializing policy definition: {0}.")]
+    Serializing(#[source] serde_json::Error),
+
     #[error("An error occurred validating policy definition: {0}")]
-    Validation(#[source] Box<dyn std::error::Error>),
+    Validation(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("Malformed variable: {0}.")]
+    MalformedVariable(#[from] crate::TokenError),
 
     #[error("An error occurred constructing the request: {0}.")]
     BadRequest(String),
+
+    #[error("The policy is too large to build: {0}.")]
+    PolicyTooLarge(String),
+
+    #[error("Statement {0} is not found in the policy.")]
+    StatementNotFound(String),
+
+    #[error(
+        "The policy does not keep a validator to validate the update. \
+         Build the policy with PolicyBuilder::build_updatable, or use PolicyUpdate::with_validator."
+    )]
+    MissingValidator,
+
+    #[error(
+        "An error occurred loading policy file {}: {}.",
+        path.display(),
+        source.to_string().trim_end_matches('.')
+    )]
+    File {
+        path: std::path::PathBuf,
+        #[source]
+        source: Box<Error>,
+    },
+
+    #[error("An error occurred reading policy file {}: {source}.", path.display())]
+    Io {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl Error {
+    /// Returns the 1-based line and column in the source document where
+    /// deserialization failed, if known.
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            Error::Deserializing(e) if e.line() > 0 => Some((e.line(), e.column())),
+            #[cfg(feature = "yaml")]
+            Error::DeserializingYaml {
+                line: Some(line),
+                column: Some(column),
+                ..
+            } => Some((*line, *column)),
+            #[cfg(feature = "toml")]
+            Error::DeserializingToml {
+                line: Some(line),
+                column: Some(column),
+                ..
+            } => Some((*line, *column)),
+            Error::File { source, .. } => source.location(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PolicyDefinition;
+
+    #[test]
+    fn error_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<Error>();
+    }
+
+    #[test]
+    fn file_error_message_test() {
+        let error = Error::File {
+            path: "policy.json".into(),
+            source: Box::new(Error::StatementNotFound("a".to_string())),
+        };
+
+        assert_eq!(
+            "An error occurred loading policy file policy.json: Statement a is not found in the policy.",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn json_error_location_test() {
+        let json = "{\n  \"statements\": [\n    { \"effect\": \"maybe\" }\n  ]\n}";
+
+        let error = PolicyDefinition::from_json(json).unwrap_err();
+
+        assert_eq!(Some((3, 23)), error.location());
+    }
 }
@@ -0,0 +1,214 @@
+//! Filters transform variable values during substitution.
+//!
+//! Filters are applied left to right, e.g. `{{identity|split:@|first}}` substitutes
+//! `alice` for the `alice@corp` identity. A filter argument follows a colon.
+//!
+//! [`DefaultSubstituter`](`crate::DefaultSubstituter`) supports the following filters:
+//! * `lower`, `upper` and `trim` - change the case or trim whitespace,
+//! * `default:x` - replaces a missing or empty value with `x`,
+//! * `split:x` - splits the value by `x`, or by whitespace if there is no argument,
+//! * `first` and `last` - take an item of a split value,
+//! * `join:x` - joins a split value with `x`.
+//!
+//! Custom filters are added with [`PolicyBuilder::with_filter`](`crate::PolicyBuilder::with_filter`).
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, LazyLock},
+};
+
+use crate::Error;
+
+/// The value of a variable as it passes through filters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// The variable is not known to the substituter.
+    Missing,
+    Str(String),
+    List(Vec<String>),
+}
+
+/// Trait to add custom filters with [`PolicyBuilder::with_filter`](`crate::PolicyBuilder::with_filter`).
+///
+/// Implemented for closures, so a filter can be registered as
+/// `|value, argument| Ok(value)`.
+///
+/// A filter should only depend on its value and argument, since decisions of variable rules
+/// of the [`DefaultSubstituter`](`crate::DefaultSubstituter`) may be cached by
+/// [`CachedPolicy`](`crate::CachedPolicy`).
+pub trait Filter {
+    /// Transforms the value. `argument` is the filter argument, e.g. `@` in `split:@`.
+    fn apply(&self, value: Value, argument: Option<&str>) -> Result<Value, Error>;
+}
+
+impl<F> Filter for F
+where
+    F: Fn(Value, Option<&str>) -> Result<Value, Error>,
+{
+    fn apply(&self, value: Value, argument: Option<&str>) -> Result<Value, Error> {
+        self(value, argument)
+    }
+}
+
+type BoxedFilter = Box<dyn Filter + Send + Sync>;
+
+static BUILTINS: LazyLock<BTreeMap<&'static str, BoxedFilter>> = LazyLock::new(|| {
+    let builtins: Vec<(&str, BoxedFilter)> = vec![
+        ("lower", Box::new(map(str::to_lowercase))),
+        ("upper", Box::new(map(str::to_uppercase))),
+        ("trim", Box::new(map(|s| s.trim().to_string()))),
+        ("default", Box::new(default)),
+        ("split", Box::new(split)),
+        (
+            "first",
+            Box::new(|value, _: Option<&str>| Ok(item(value, <[String]>::first))),
+        ),
+        (
+            "last",
+            Box::new(|value, _: Option<&str>| Ok(item(value, <[String]>::last))),
+        ),
+        ("join", Box::new(join)),
+    ];
+    builtins.into_iter().collect()
+});
+
+/// Returns the built-in filter with the name.
+pub(crate) fn builtin(name: &str) -> Option<&'static (dyn Filter + Send + Sync)> {
+    BUILTINS.get(name).map(AsRef::as_ref)
+}
+
+/// Returns the names of the built-in filters.
+pub(crate) fn builtin_names() -> impl Iterator<Item = &'static str> {
+    BUILTINS.keys().copied()
+}
+
+/// Custom filters added with [`PolicyBuilder::with_filter`](`crate::PolicyBuilder::with_filter`).
+/// They take precedence over the built-in filters with the same name.
+#[derive(Clone, Default)]
+pub(crate) struct Filters(BTreeMap<String, Arc<dyn Filter + Send + Sync>>);
+
+impl Filters {
+    pub fn insert(&mut self, name: String, filter: impl Filter + Send + Sync + 'static) {
+        self.0.insert(name, Arc::new(filter));
+    }
+
+    /// Returns the custom or the built-in filter with the name.
+    pub fn get(&self, name: &str) -> Option<&(dyn Filter + Send + Sync)> {
+        match self.0.get(name) {
+            Some(filter) => Some(filter.as_ref()),
+            None => builtin(name),
+        }
+    }
+
+    /// Returns the names of the custom filters.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Filters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.keys()).finish()
+    }
+}
+
+/// Creates a filter that transforms every string of the value.
+fn map(
+    f: impl Fn(&str) -> String + Send + Sync,
+) -> impl Fn(Value, Option<&str>) -> Result<Value, Error> + Send + Sync {
+    move |value, _| {
+        Ok(match value {
+            Value::Missing => Value::Missing,
+            Value::Str(s) => Value::Str(f(&s)),
+            Value::List(items) => Value::List(items.iter().map(|s| f(s)).collect()),
+        })
+    }
+}
+
+#[allow(clippy::unnecessary_wraps)]
+fn default(value: Value, argument: Option<&str>) -> Result<Value, Error> {
+    Ok(match value {
+        Value::Missing => Value::Str(argument.unwrap_or_default().to_string()),
+        Value::Str(s) if s.is_empty() => Value::Str(argument.unwrap_or_default().to_string()),
+        Value::List(items) if items.is_empty() => {
+            Value::Str(argument.unwrap_or_default().to_string())
+        }
+        value => value,
+    })
+}
+
+#[allow(clippy::unnecessary_wraps)]
+fn split(value: Value, argument: Option<&str>) -> Result<Value, Error> {
+    let split = |s: &str| -> Vec<String> {
+        match argument {
+            Some(separator) if !separator.is_empty() => {
+                s.split(separator).map(ToString::to_string).collect()
+            }
+            _ => s.split_whitespace().map(ToString::to_string).collect(),
+        }
+    };
+
+    Ok(match value {
+        Value::Missing => Value::Missing,
+        Value::Str(s) => Value::List(split(&s)),
+        Value::List(items) => Value::List(items.iter().flat_map(|s| split(s)).collect()),
+    })
+}
+
+fn item(value: Value, f: impl Fn(&[String]) -> Option<&String>) -> Value {
+    match value {
+        Value::List(items) => f(&items).map_or(Value::Missing, |s| Value::Str(s.clone())),
+        value => value,
+    }
+}
+
+#[allow(clippy::unnecessary_wraps)]
+fn join(value: Value, argument: Option<&str>) -> Result<Value, Error> {
+    Ok(match value {
+        Value::List(items) => Value::Str(items.join(argument.unwrap_or_default())),
+        value => value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    fn apply(name: &str, value: Value, argument: Option<&str>) -> Value {
+        builtin(name).unwrap().apply(value, argument).unwrap()
+    }
+
+    fn str(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+
+    fn list(items: &[&str]) -> Value {
+        Value::List(items.iter().map(ToString::to_string).collect())
+    }
+
+    #[test_case("lower", str("Alice"), None, str("alice"); "lower")]
+    #[test_case("upper", list(&["a", "b"]), None, list(&["A", "B"]); "upper list")]
+    #[test_case("trim", str(" a "), None, str("a"); "trim")]
+    #[test_case("default", Value::Missing, Some("public"), str("public"); "default missing")]
+    #[test_case("default", str(""), Some("public"), str("public"); "default empty")]
+    #[test_case("default", str("corp"), Some("public"), str("corp"); "default present")]
+    #[test_case("split", str("alice@corp"), Some("@"), list(&["alice", "corp"]); "split")]
+    #[test_case("split", str("a b  c"), None, list(&["a", "b", "c"]); "split whitespace")]
+    #[test_case("first", list(&["alice", "corp"]), None, str("alice"); "first")]
+    #[test_case("last", list(&["alice", "corp"]), None, str("corp"); "last")]
+    #[test_case("first", list(&[]), None, Value::Missing; "first of empty")]
+    #[test_case("first", str("alice"), None, str("alice"); "first of string")]
+    #[test_case("join", list(&["a", "b"]), Some("/"), str("a/b"); "join")]
+    #[test_case("lower", Value::Missing, None, Value::Missing; "missing passes through")]
+    #[allow(clippy::needless_pass_by_value)]
+    fn builtin_filter_test(name: &str, value: Value, argument: Option<&str>, expected: Value) {
+        assert_eq!(expected, apply(name, value, argument));
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    fmt,
+    ops::Deref,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use arc_swap::ArcSwap;
+
+use crate::{
+    normalizer, Decision, Normalizer, Policy, Request, ResourceMatcher, Result, Substituter,
+};
+
+/// A shared [`Policy`] that can be replaced while it is evaluated.
+///
+/// Reads are lock-free. Each replacement increments the generation of the policy,
+/// so that callers and caches know which version made a decision.
+///
+/// ```rust
+/// use allow_me::{Decision, PolicyBuilder, PolicyHandle, Request};
+///
+/// let build = |effect: &str| {
+///     let json = format!(
+///         r#"{{ "statements": [ {{
+///             "effect": "{}",
+///             "identities": [ "actor_a" ],
+///             "operations": [ "write" ],
+///             "resources": [ "resource_1" ]
+///         }} ] }}"#,
+///         effect
+///     );
+///     PolicyBuilder::from_json(&json).build().unwrap()
+/// };
+///
+/// let handle = PolicyHandle::new(build("allow"));
+/// let request = Request::new("actor_a", "write", "resource_1").unwrap();
+/// assert_eq!(Decision::Allowed, handle.evaluate(&request).unwrap());
+///
+/// assert_eq!(1, handle.replace(build("deny")));
+/// let snapshot = handle.load();
+/// assert_eq!(1, snapshot.generation());
+/// assert_eq!(Decision::Denied, snapshot.evaluate(&request).unwrap());
+/// ```
+pub struct PolicyHandle<R, S, N = normalizer::Default> {
+    current: ArcSwap<PolicySnapshot<R, S, N>>,
+    /// Serializes replacements, so that generations increase.
+    writer: Mutex<()>,
+}
+
+/// A policy loaded from a [`PolicyHandle`] with its generation.
+pub struct PolicySnapshot<R, S, N = normalizer::Default> {
+    generation: u64,
+    policy: Policy<R, S, N>,
+}
+
+impl<R, S, N> PolicyHandle<R, S, N> {
+    /// Creates a handle with the policy as generation 0.
+    pub fn new(policy: Policy<R, S, N>) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(PolicySnapshot {
+                generation: 0,
+                policy,
+            }),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// Returns the current policy. The snapshot is not affected by later replacements.
+    pub fn load(&self) -> Arc<PolicySnapshot<R, S, N>> {
+        self.current.load_full()
+    }
+
+    /// Returns the generation of the current policy.
+    pub fn generation(&self) -> u64 {
+        self.current.load().generation
+    }
+
+    /// Atomically replaces the policy. Returns the generation of the new policy.
+    pub fn replace(&self, policy: Policy<R, S, N>) -> u64 {
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let generation = self.current.load().generation + 1;
+        self.current
+            .store(Arc::new(PolicySnapshot { generation, policy }));
+        generation
+    }
+}
+
+impl<R, S, N, RC> PolicyHandle<R, S, N>
+where
+    R: ResourceMatcher<Context = RC>,
+    S: Substituter<Context = RC>,
+    N: Normalizer,
+{
+    /// Evaluates the request with the current policy.
+    pub fn evaluate(&self, request: &Request<'_, RC>) -> Result<Decision> {
+        self.current.load().policy.evaluate(request)
+    }
+}
+
+impl<R, S, N> fmt::Debug for PolicyHandle<R, S, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyHandle")
+            .field("generation", &self.generation())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R, S, N> PolicySnapshot<R, S, N> {
+    /// The number of replacements of the handle before this policy.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The policy of the generation. The snapshot also dereferences to it.
+    pub fn policy(&self) -> &Policy<R, S, N> {
+        &self.policy
+    }
+}
+
+impl<R, S, N> Deref for PolicySnapshot<R, S, N> {
+    type Target = Policy<R, S, N>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.policy
+    }
+}
+
+impl<R, S, N> fmt::Debug for PolicySnapshot<R, S, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicySnapshot")
+            .field("generation", &self.generation)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::{matcher::Default, DefaultSubstituter, PolicyBuilder};
+
+    use super::*;
+
+    fn build(effect: &str) -> Policy<Default, DefaultSubstituter> {
+        let json = format!(
+            r#"{{
+                "statements": [
+                    {{
+                        "effect": "{effect}",
+                        "identities": [ "actor_a" ],
+                        "operations": [ "write" ],
+                        "resources": [ "resource_1" ]
+                    }}
+                ]
+            }}"#
+        );
+        PolicyBuilder::from_json(&json).build().unwrap()
+    }
+
+    #[test]
+    fn replace_test() {
+        let handle = PolicyHandle::new(build("allow"));
+        let request = Request::new("actor_a", "write", "resource_1").unwrap();
+        let snapshot = handle.load();
+
+        assert_eq!(1, handle.replace(build("deny")));
+
+        assert_eq!(1, handle.generation());
+        assert_eq!(Decision::Denied, handle.evaluate(&request).unwrap());
+        // a snapshot keeps the policy it was loaded with.
+        assert_eq!(0, snapshot.generation());
+        assert_eq!(Decision::Allowed, snapshot.evaluate(&request).unwrap());
+    }
+
+    #[test]
+    fn concurrent_replace_test() {
+        let handle = Arc::new(PolicyHandle::new(build("allow")));
+
+        let threads = (0..4)
+            .map(|i| {
+                let handle = Arc::clone(&handle);
+                thread::spawn(move || {
+                    let request = Request::new("actor_a", "write", "resource_1").unwrap();
+                    for _ in 0..10 {
+                        handle.replace(build(if i % 2 == 0 { "allow" } else { "deny" }));
+                        handle.evaluate(&request).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(40, handle.generation());
+    }
+}
@@ -3,9 +3,10 @@
 //! `Resource` (R) model. Evaluate requests against your policy rules.
 //!
 //! Supports the following customizations:
-//! * variable rules and custom variables,
+//! * variable rules, custom variables and variable filters,
 //! * custom resource matching,
-//! * custom validation,
+//! * request and policy value normalization,
+//! * custom validation, linting and static analysis of shadowed statements,
 //! * default decision if no rules match.
 //!
 //! ## Examples
@@ -56,15 +57,34 @@
     clippy::missing_errors_doc
 )]
 
+mod analyzer;
+mod cache;
 mod core;
 mod errors;
+pub mod filter;
+mod handle;
+mod linter;
 pub mod matcher;
+pub mod normalizer;
 mod substituter;
+mod tokenizer;
 mod validator;
+mod watcher;
 
-pub use crate::core::{Decision, Effect, Policy, Request};
+pub use crate::analyzer::{PolicyAnalyzer, Shadowing};
+pub use crate::cache::CachedPolicy;
+pub use crate::core::{Decision, Effect, MemoryUsage, Policy, Request};
 pub use crate::core::{PolicyBuilder, PolicyDefinition, Statement};
+pub use crate::core::{PolicyDefinitionBuilder, PolicyUpdate, StatementBuilder};
 pub use crate::errors::{Error, Result};
+pub use crate::handle::{PolicyHandle, PolicySnapshot};
+pub use crate::linter::{Lint, Linter, SUPPRESS_KEY};
 pub use crate::matcher::ResourceMatcher;
+pub use crate::normalizer::Normalizer;
+#[allow(deprecated)]
 pub use crate::substituter::{DefaultSubstituter, Substituter, VariableIter};
-pub use crate::validator::{DefaultValidator, PolicyValidator};
+pub use crate::tokenizer::{Expression, Segment, Template, Token, TokenError, Tokenizer, Variable};
+pub use crate::validator::{
+    DefaultValidator, Diagnostic, Field, PolicyValidator, Severity, ValidatorError,
+};
+pub use crate::watcher::{PolicyWatcher, WatcherGuard};
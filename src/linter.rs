@@ -0,0 +1,415 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::{
+    matcher::MatchSemantics,
+    substituter::{is_supported, ANY_VAR},
+    validator::Severity,
+    DefaultSubstituter, Diagnostic, Effect, Expression, Field, PolicyDefinition, PolicyValidator,
+    Statement, Substituter, Token, Tokenizer, ValidatorError,
+};
+
+/// The statement metadata key with the ids of the lints that are not reported for the statement.
+pub const SUPPRESS_KEY: &str = "suppress";
+
+/// A risky policy pattern reported by the [`Linter`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lint {
+    /// An allow statement applies to any identity.
+    AnyIdentityAllow,
+    /// A statement has no resources, so it is matched against an empty resource.
+    MissingResources,
+    /// A resource used as a prefix does not end with a separator, e.g. `/home/al` matches `/home/alice`.
+    PrefixWithoutSeparator,
+    /// A variable that [`DefaultSubstituter`](`crate::DefaultSubstituter`) leaves unsubstituted.
+    UnknownVariable,
+    /// A `{{` without a matching `}}`, or the other way around.
+    ///
+    /// It can not be suppressed in the statement metadata, since
+    /// [`PolicyBuilder`](`crate::PolicyBuilder`) fails on such values regardless of the linter.
+    UnbalancedBraces,
+}
+
+impl Lint {
+    /// All lints.
+    pub const ALL: [Lint; 5] = [
+        Lint::AnyIdentityAllow,
+        Lint::MissingResources,
+        Lint::PrefixWithoutSeparator,
+        Lint::UnknownVariable,
+        Lint::UnbalancedBraces,
+    ];
+
+    /// The identifier of the lint, used in diagnostics and for suppression.
+    pub fn id(self) -> &'static str {
+        match self {
+            Lint::AnyIdentityAllow => "any-identity-allow",
+            Lint::MissingResources => "missing-resources",
+            Lint::PrefixWithoutSeparator => "prefix-without-separator",
+            Lint::UnknownVariable => "unknown-variable",
+            Lint::UnbalancedBraces => "unbalanced-braces",
+        }
+    }
+
+    /// The severity of the lint unless configured otherwise.
+    pub fn default_severity(self) -> Severity {
+        match self {
+            Lint::AnyIdentityAllow | Lint::MissingResources | Lint::PrefixWithoutSeparator => {
+                Severity::Warn
+            }
+            Lint::UnknownVariable | Lint::UnbalancedBraces => Severity::Fail,
+        }
+    }
+}
+
+/// Flags risky patterns in a policy definition.
+///
+/// Unlike [`DefaultValidator`](`crate::DefaultValidator`), the linter reports statements that are
+/// valid but likely wrong. Every [`Lint`] has a severity, and, except for [`Lint::UnbalancedBraces`],
+/// can be suppressed for a statement by listing its id in the statement metadata, available
+/// since schema version 2:
+///
+/// ```json
+/// "metadata": { "suppress": [ "any-identity-allow" ] }
+/// ```
+#[derive(Debug)]
+pub struct Linter {
+    semantics: MatchSemantics,
+    separator: char,
+    severities: BTreeMap<Lint, Severity>,
+}
+
+impl Linter {
+    /// Creates a linter for policies evaluated by a matcher with the given semantics.
+    pub fn new(semantics: MatchSemantics) -> Self {
+        Self {
+            semantics,
+            separator: '/',
+            severities: Lint::ALL
+                .iter()
+                .map(|lint| (*lint, lint.default_severity()))
+                .collect(),
+        }
+    }
+
+    /// Specifies how the lint is reported.
+    #[must_use]
+    pub fn with_severity(mut self, lint: Lint, severity: Severity) -> Self {
+        self.severities.insert(lint, severity);
+        self
+    }
+
+    /// Specifies the resource separator expected at the end of prefixes. Defaults to `/`.
+    #[must_use]
+    pub fn with_separator(mut self, separator: char) -> Self {
+        self.separator = separator;
+        self
+    }
+
+    /// Returns all problems found in the policy definition, with their severities.
+    pub fn lint(&self, definition: &PolicyDefinition) -> Vec<(Severity, Diagnostic)> {
+        let mut result = vec![];
+        for statement in definition.statements() {
+            let suppressed = suppressed(statement);
+            for (lint, diagnostic) in self.visit_statement(statement) {
+                let severity = self.severities[&lint];
+                if severity != Severity::Ignore
+                    && (lint == Lint::UnbalancedBraces || !suppressed.contains(&lint.id()))
+                {
+                    result.push((severity, diagnostic.with_code(lint.id())));
+                }
+            }
+        }
+        result
+    }
+
+    fn diagnostics(&self, definition: &PolicyDefinition, severity: Severity) -> Vec<Diagnostic> {
+        self.lint(definition)
+            .into_iter()
+            .filter(|(s, _)| *s == severity)
+            .map(|(_, diagnostic)| diagnostic)
+            .collect()
+    }
+
+    fn visit_statement(&self, statement: &Statement) -> Vec<(Lint, Diagnostic)> {
+        let mut result = vec![];
+        if statement.effect() == Effect::Allow {
+            for (index, identity) in statement.identities().iter().enumerate() {
+                if identity == ANY_VAR {
+                    let diagnostic = Diagnostic::new(
+                        statement,
+                        Field::Identities,
+                        "Statement allows any identity",
+                    );
+                    result.push((
+                        Lint::AnyIdentityAllow,
+                        diagnostic.with_value(index, identity),
+                    ));
+                }
+            }
+        }
+
+        if statement.resources().is_empty() {
+            let diagnostic = Diagnostic::new(
+                statement,
+                Field::Resources,
+                "Statement has no resources, so it is matched against an empty resource",
+            );
+            result.push((Lint::MissingResources, diagnostic));
+        }
+
+        if self.semantics == MatchSemantics::StartsWith {
+            for (index, resource) in statement.resources().iter().enumerate() {
+                if !resource.is_empty()
+                    && resource != ANY_VAR
+                    && !resource.ends_with(self.separator)
+                {
+                    let diagnostic = Diagnostic::new(
+                        statement,
+                        Field::Resources,
+                        format!(
+                            "Resource prefix does not end with {:?} and matches resources that only start the same way",
+                            self.separator
+                        ),
+                    );
+                    result.push((
+                        Lint::PrefixWithoutSeparator,
+                        diagnostic.with_value(index, resource),
+                    ));
+                }
+            }
+        }
+
+        let fields = [
+            (Field::Identities, statement.identities()),
+            (Field::Operations, statement.operations()),
+            (Field::Resources, statement.resources()),
+        ];
+        for (field, values) in &fields {
+            for (index, value) in values.iter().enumerate() {
+                result.extend(visit_variables(statement, *field, index, value));
+            }
+        }
+        result
+    }
+}
+
+impl PolicyValidator for Linter {
+    type Error = ValidatorError;
+
+    fn validate(&self, definition: &PolicyDefinition) -> Result<(), Self::Error> {
+        let errors = self.diagnostics(definition, Severity::Fail);
+        if !errors.is_empty() {
+            return Err(ValidatorError::ValidationSummary(errors));
+        }
+        Ok(())
+    }
+
+    fn warnings(&self, definition: &PolicyDefinition) -> Vec<Diagnostic> {
+        self.diagnostics(definition, Severity::Warn)
+    }
+}
+
+fn visit_variables(
+    statement: &Statement,
+    field: Field,
+    index: usize,
+    value: &str,
+) -> Vec<(Lint, Diagnostic)> {
+    let mut result = vec![];
+    let mut escaped = 0;
+    for token in Tokenizer::new(value) {
+        let message = match token {
+            Ok(Token::Variable(variable)) => {
+                let expression = Expression::parse(variable);
+                if DefaultSubstituter
+                    .variables(field)
+                    .is_some_and(|known| !is_supported(known, expression.name()))
+                {
+                    let diagnostic = Diagnostic::new(
+                        statement,
+                        field,
+                        format!(
+                            "Variable {variable} is not supported here and is left unsubstituted"
+                        ),
+                    );
+                    result.push((Lint::UnknownVariable, diagnostic.with_value(index, value)));
+                }
+                continue;
+            }
+            // an escaped `{{` is the only way to get one in a literal.
+            Ok(Token::Literal("{{")) => {
+                escaped += 1;
+                continue;
+            }
+            Ok(Token::Literal(literal)) if literal.contains("}}") => {
+                if escaped > 0 {
+                    escaped -= 1;
+                    continue;
+                }
+                "Closing }} has no matching {{".to_string()
+            }
+            Ok(Token::Literal(_)) => continue,
+            Err(error) => error.to_string(),
+        };
+        let diagnostic = Diagnostic::new(statement, field, message);
+        result.push((Lint::UnbalancedBraces, diagnostic.with_value(index, value)));
+    }
+    result
+}
+
+/// Returns the lint ids listed under the [`SUPPRESS_KEY`] of the statement metadata.
+fn suppressed(statement: &Statement) -> Vec<&str> {
+    match statement.metadata().get(SUPPRESS_KEY) {
+        Some(Value::String(id)) => vec![id.as_str()],
+        Some(Value::Array(ids)) => ids.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::{DefaultValidator, Error, PolicyBuilder};
+
+    fn codes(linter: &Linter, definition: &PolicyDefinition) -> Vec<String> {
+        linter
+            .lint(definition)
+            .into_iter()
+            .map(|(_, diagnostic)| diagnostic.code().unwrap().to_string())
+            .collect()
+    }
+
+    #[test_case(Effect::Allow, "{{any}}", "read", "/home/", &["any-identity-allow"]; "any identity allow")]
+    #[test_case(Effect::Deny, "{{any}}", "read", "/home/", &[]; "any identity deny")]
+    #[test_case(Effect::Allow, "actor_a", "read", "/home", &["prefix-without-separator"]; "prefix without separator")]
+    #[test_case(Effect::Allow, "actor_a", "read", "/home/{{identity}}/", &[]; "variable prefix with separator")]
+    #[test_case(Effect::Allow, "actor_a", "read", "{{any}}", &[]; "any resource")]
+    #[test_case(Effect::Allow, "actor_a", "{{resource}}", "/home/", &["unknown-variable"]; "unknown variable")]
+    #[test_case(Effect::Allow, "{{operation}}", "read", "/home/", &["unknown-variable"]; "variable not supported in identities")]
+    #[test_case(Effect::Allow, "actor_a", "read", "/home/{{identity}/", &["unbalanced-braces"]; "missing closing braces")]
+    #[test_case(Effect::Allow, "actor_a", "read", "/home/identity}}/", &["unbalanced-braces"]; "missing opening braces")]
+    #[test_case(Effect::Allow, "actor_a", "read", r"/home/\{{identity}}/", &[]; "escaped braces")]
+    #[test_case(Effect::Allow, "actor_a", "read", "/{{a{{identity}}/", &["unbalanced-braces"]; "nested braces")]
+    fn lint_test(
+        effect: Effect,
+        identity: &str,
+        operation: &str,
+        resource: &str,
+        expected: &[&str],
+    ) {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.effect(effect)
+                    .identity(identity)
+                    .operation(operation)
+                    .resource(resource)
+            })
+            .build();
+
+        let codes = codes(&Linter::new(MatchSemantics::StartsWith), &definition);
+
+        assert_eq!(expected, codes.as_slice());
+    }
+
+    #[test]
+    fn missing_resources_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| s.allow().identity("actor_a").operation("connect"))
+            .build();
+
+        let diagnostics = Linter::new(MatchSemantics::Equality).lint(&definition);
+
+        assert_eq!(1, diagnostics.len());
+        assert_eq!(Severity::Warn, diagnostics[0].0);
+        assert_eq!(
+            "statement #0, /statements/0/resources: [missing-resources] \
+             Statement has no resources, so it is matched against an empty resource",
+            diagnostics[0].1.to_string()
+        );
+    }
+
+    #[test]
+    fn suppressed_lint_test() {
+        let json = r#"{
+            "schemaVersion": 2,
+            "statements": [
+                {
+                    "effect": "allow",
+                    "identities": [ "{{any}}" ],
+                    "operations": [ "read" ],
+                    "metadata": {
+                        "suppress": [ "any-identity-allow", "missing-resources" ]
+                    }
+                },
+                {
+                    "effect": "allow",
+                    "identities": [ "{{any}}" ],
+                    "operations": [ "read" ],
+                    "resources": [ "/public/" ],
+                    "metadata": {
+                        "suppress": "missing-resources"
+                    }
+                }
+            ]
+        }"#;
+
+        let policy = PolicyBuilder::from_json(json)
+            .with_validator((DefaultValidator, Linter::new(MatchSemantics::StartsWith)))
+            .build()
+            .unwrap();
+
+        assert_eq!(1, policy.warnings().len());
+        assert_eq!(1, policy.warnings()[0].statement());
+        assert_eq!(Some("any-identity-allow"), policy.warnings()[0].code());
+    }
+
+    #[test]
+    fn unbalanced_braces_not_suppressed_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("actor_a")
+                    .operation("read")
+                    .resource("/home/{{identity/")
+                    .metadata("suppress", serde_json::json!(["unbalanced-braces"]))
+            })
+            .build();
+
+        let diagnostics = Linter::new(MatchSemantics::StartsWith).lint(&definition);
+
+        assert_eq!(1, diagnostics.len());
+        assert_eq!(Some("unbalanced-braces"), diagnostics[0].1.code());
+    }
+
+    #[test]
+    fn lint_severity_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| {
+                s.allow()
+                    .identity("{{any}}")
+                    .operation("read")
+                    .resource("/home/{{user}}/")
+            })
+            .build();
+
+        let result = PolicyBuilder::from_definition(definition)
+            .with_validator(
+                Linter::new(MatchSemantics::StartsWith)
+                    .with_severity(Lint::AnyIdentityAllow, Severity::Fail)
+                    .with_severity(Lint::UnknownVariable, Severity::Ignore),
+            )
+            .build();
+
+        let error = assert_matches!(result, Err(Error::Validation(e)) => e);
+        assert_eq!(
+            "statement #0, /statements/0/identities/0: [any-identity-allow] \
+             Statement allows any identity (value: \"{{any}}\")",
+            error.to_string()
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::{collections::BTreeMap, fmt};
+
 use crate::core::Request;
 
 /// Trait to extend [`Policy`](`crate::Policy`) resource matching.
@@ -7,7 +9,16 @@ pub trait ResourceMatcher {
 
     /// This method is being called by [`Policy`](`crate::Policy`) when it tries to match a [`Request`] to
     /// a resource in the policy rules.
-    fn do_match(&self, context: &Request<Self::Context>, input: &str, policy: &str) -> bool;
+    fn do_match(&self, context: &Request<'_, Self::Context>, input: &str, policy: &str) -> bool;
+
+    /// Describes how the matcher compares resources, if it behaves like a built-in one.
+    ///
+    /// [`PolicyBuilder`](`crate::PolicyBuilder`) uses it to index static rules,
+    /// e.g. resources of [`MatchSemantics::StartsWith`] matchers are looked up in a prefix trie
+    /// instead of calling `do_match` for each of them. `None` means no index is used.
+    fn semantics(&self) -> Option<MatchSemantics> {
+        None
+    }
 }
 
 /// Default matcher uses equality check for resource matching.
@@ -17,9 +28,13 @@ pub struct Default;
 impl ResourceMatcher for Default {
     type Context = ();
 
-    fn do_match(&self, _context: &Request<Self::Context>, input: &str, policy: &str) -> bool {
+    fn do_match(&self, _context: &Request<'_, Self::Context>, input: &str, policy: &str) -> bool {
         input == policy
     }
+
+    fn semantics(&self) -> Option<MatchSemantics> {
+        Some(MatchSemantics::Equality)
+    }
 }
 
 /// Resource matcher that uses "star-with" check for resource matching.
@@ -30,7 +45,338 @@ pub struct StartsWith;
 impl ResourceMatcher for StartsWith {
     type Context = ();
 
-    fn do_match(&self, _context: &Request<Self::Context>, input: &str, policy: &str) -> bool {
+    fn do_match(&self, _context: &Request<'_, Self::Context>, input: &str, policy: &str) -> bool {
         input.starts_with(policy)
     }
+
+    fn semantics(&self) -> Option<MatchSemantics> {
+        Some(MatchSemantics::StartsWith)
+    }
+}
+
+/// Resource matcher that treats resources as `/`-separated paths.
+///
+/// Input matches the policy if, after normalization, every segment of the policy
+/// path is a leading segment of the input path. Permissions granted on a directory
+/// are therefore inherited by its children only: `/home/al` does not match `/home/alice`.
+///
+/// Both values are normalized before matching: duplicate slashes and `.` segments are
+/// removed and `..` segments are resolved. Inputs that traverse above the root
+/// (e.g. `/../etc`) never match.
+#[derive(Debug)]
+pub struct PathPrefix;
+
+impl ResourceMatcher for PathPrefix {
+    type Context = ();
+
+    fn do_match(&self, _context: &Request<'_, Self::Context>, input: &str, policy: &str) -> bool {
+        match (NormalizedPath::new(input), NormalizedPath::new(policy)) {
+            (Some(input), Some(policy)) => input.starts_with(&policy),
+            _ => false,
+        }
+    }
+
+    fn semantics(&self) -> Option<MatchSemantics> {
+        Some(MatchSemantics::PathPrefix)
+    }
+}
+
+/// Resource matcher that treats the policy value as a glob pattern.
+/// `*` matches any sequence of characters, including an empty one and `/`.
+#[derive(Debug)]
+pub struct Glob;
+
+impl ResourceMatcher for Glob {
+    type Context = ();
+
+    fn do_match(&self, _context: &Request<'_, Self::Context>, input: &str, policy: &str) -> bool {
+        glob_match(policy.as_bytes(), input.as_bytes())
+    }
+
+    fn semantics(&self) -> Option<MatchSemantics> {
+        Some(MatchSemantics::Glob)
+    }
+}
+
+fn glob_match(pattern: &[u8], input: &[u8]) -> bool {
+    // position of the last `*` in the pattern and the input position it was tried at.
+    let mut backtrack: Option<(usize, usize)> = None;
+    let (mut p, mut i) = (0, 0);
+    while i < input.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, i));
+                p += 1;
+            }
+            Some(c) if *c == input[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match backtrack {
+                // let the last `*` consume one more character.
+                Some((star, start)) => {
+                    backtrack = Some((star, start + 1));
+                    p = star + 1;
+                    i = start + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Resource matcher that captures segments of the input into named variables.
+///
+/// Policy values are `/`-separated patterns:
+/// * `{name}` captures exactly one non-empty segment,
+/// * `*` matches exactly one non-empty segment,
+/// * `**` as the last segment matches any remaining segments, including none,
+/// * other segments must be equal.
+///
+/// A capture used more than once must capture the same value. Conditions compare
+/// captured values to other request data:
+///
+/// ```rust
+/// use allow_me::{matcher::Captures, Request, ResourceMatcher};
+///
+/// let matcher = Captures::new().with_condition("user", |user, request: &Request<'_, ()>| {
+///     user == request.identity()
+/// });
+/// let pattern = "/tenants/{tenant}/users/{user}/**";
+///
+/// let request = Request::new("alice", "read", "/tenants/corp/users/alice/notes").unwrap();
+/// assert!(matcher.do_match(&request, request.resource(), pattern));
+///
+/// let request = Request::new("alice", "read", "/tenants/corp/users/bob/notes").unwrap();
+/// assert!(!matcher.do_match(&request, request.resource(), pattern));
+/// ```
+pub struct Captures<C = ()> {
+    conditions: BTreeMap<String, Condition<C>>,
+}
+
+type Condition<C> = Box<dyn Fn(&str, &Request<'_, C>) -> bool + Send + Sync>;
+
+impl<C> Captures<C> {
+    pub fn new() -> Self {
+        Self {
+            conditions: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a condition for the captured value, e.g. `user == identity`.
+    /// A pattern matches only if conditions of all its captures hold.
+    #[must_use]
+    pub fn with_condition(
+        mut self,
+        name: impl Into<String>,
+        condition: impl Fn(&str, &Request<'_, C>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.conditions.insert(name.into(), Box::new(condition));
+        self
+    }
+
+    /// Matches the input to the pattern and returns the captured values by name,
+    /// in the order they appear in the pattern.
+    pub fn captures<'a>(
+        &self,
+        context: &Request<'_, C>,
+        input: &'a str,
+        policy: &'a str,
+    ) -> Option<Vec<(&'a str, &'a str)>> {
+        let mut captures: Vec<(&str, &str)> = vec![];
+        let mut segments = input.split('/');
+        let mut patterns = policy.split('/').peekable();
+        while let Some(pattern) = patterns.next() {
+            if pattern == "**" && patterns.peek().is_none() {
+                return Some(captures);
+            }
+            let segment = segments.next()?;
+            match capture_name(pattern) {
+                Some(name) => {
+                    if segment.is_empty() {
+                        return None;
+                    }
+                    match captures.iter().find(|(captured, _)| *captured == name) {
+                        Some((_, value)) if *value != segment => return None,
+                        Some(_) => {}
+                        None => {
+                            if let Some(condition) = self.conditions.get(name) {
+                                if !condition(segment, context) {
+                                    return None;
+                                }
+                            }
+                            captures.push((name, segment));
+                        }
+                    }
+                }
+                None if pattern == "*" && segment.is_empty() => return None,
+                None if pattern != "*" && pattern != segment => return None,
+                None => {}
+            }
+        }
+        segments.next().is_none().then_some(captures)
+    }
+}
+
+impl<C> std::default::Default for Captures<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> fmt::Debug for Captures<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Captures")
+            .field("conditions", &self.conditions.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<C> ResourceMatcher for Captures<C> {
+    type Context = C;
+
+    fn do_match(&self, context: &Request<'_, Self::Context>, input: &str, policy: &str) -> bool {
+        self.captures(context, input, policy).is_some()
+    }
+
+    fn semantics(&self) -> Option<MatchSemantics> {
+        Some(MatchSemantics::Captures)
+    }
+}
+
+/// Returns the capture name of a `{name}` pattern segment.
+fn capture_name(pattern: &str) -> Option<&str> {
+    pattern
+        .strip_prefix('{')
+        .and_then(|name| name.strip_suffix('}'))
+        .filter(|name| !name.is_empty() && !name.contains(['{', '}']))
+}
+
+/// Describes how a [`ResourceMatcher`] compares resources.
+///
+/// Used by [`PolicyAnalyzer`](`crate::PolicyAnalyzer`) to reason about statements
+/// without evaluating requests.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MatchSemantics {
+    /// Resources match if they are equal, as in [`Default`](`struct@Default`).
+    Equality,
+    /// Input matches if it starts with the policy value, as in [`StartsWith`].
+    StartsWith,
+    /// Input matches if it is inside the policy path, as in [`PathPrefix`].
+    PathPrefix,
+    /// Input matches the policy glob pattern, as in [`Glob`].
+    Glob,
+    /// Input matches the policy pattern with captures, as in [`Captures`].
+    Captures,
+}
+
+/// A path split into segments with `.`, `..` and empty segments resolved.
+#[derive(Debug, PartialEq)]
+pub(crate) struct NormalizedPath<'a> {
+    absolute: bool,
+    segments: Vec<&'a str>,
+}
+
+impl<'a> NormalizedPath<'a> {
+    /// Returns `None` if the path escapes its root via `..` segments.
+    pub(crate) fn new(path: &'a str) -> Option<Self> {
+        let mut segments = Vec::new();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop()?;
+                }
+                _ => segments.push(segment),
+            }
+        }
+
+        Some(Self {
+            absolute: path.starts_with('/'),
+            segments,
+        })
+    }
+
+    pub(crate) fn is_absolute(&self) -> bool {
+        self.absolute
+    }
+
+    pub(crate) fn segments(&self) -> &[&'a str] {
+        &self.segments
+    }
+
+    pub(crate) fn starts_with(&self, prefix: &NormalizedPath<'_>) -> bool {
+        self.absolute == prefix.absolute && self.segments.starts_with(&prefix.segments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("/home/alice", "/home/alice", true; "same path")]
+    #[test_case("/home/alice/notes.txt", "/home/alice", true; "child path")]
+    #[test_case("/home/alice/notes.txt", "/home/alice/", true; "policy trailing slash")]
+    #[test_case("/home/alice/", "/home/alice", true; "input trailing slash")]
+    #[test_case("/home/alice", "/home/al", false; "partial segment")]
+    #[test_case("/home", "/home/alice", false; "parent path")]
+    #[test_case("//home/./alice//notes.txt", "/home/alice", true; "duplicate slashes and dots")]
+    #[test_case("/home/bob/../alice/notes.txt", "/home/alice", true; "resolved parent segment")]
+    #[test_case("/home/alice/../bob", "/home/alice", false; "traversal out of prefix")]
+    #[test_case("/home/alice/../../../etc", "/", false; "traversal above root")]
+    #[test_case("/anything", "/", true; "root policy")]
+    #[test_case("home/alice", "/home/alice", false; "relative input")]
+    fn path_prefix_test(input: &str, policy: &str, expected: bool) {
+        let request = Request::new("some_identity", "some_operation", input).unwrap();
+
+        assert_eq!(expected, PathPrefix.do_match(&request, input, policy));
+    }
+
+    #[test_case("/tenants/corp/users/alice", "/tenants/{tenant}/users/{user}", true; "captures")]
+    #[test_case("/tenants/corp/users/alice/notes", "/tenants/{tenant}/users/{user}", false; "extra segment")]
+    #[test_case("/tenants/corp/users/alice/notes", "/tenants/{tenant}/users/{user}/**", true; "double star")]
+    #[test_case("/tenants/corp/users/alice", "/tenants/{tenant}/users/{user}/**", true; "double star matches none")]
+    #[test_case("/tenants/corp/users/alice", "/tenants/{tenant}/**/{user}", false; "double star in the middle")]
+    #[test_case("/tenants//users/alice", "/tenants/{tenant}/users/{user}", false; "empty capture")]
+    #[test_case("/tenants/corp/admins/alice", "/tenants/*/admins/{user}", true; "single star")]
+    #[test_case("/a/corp/b/corp", "/a/{x}/b/{x}", true; "repeated capture")]
+    #[test_case("/a/corp/b/other", "/a/{x}/b/{x}", false; "repeated capture differs")]
+    #[test_case("/a/{x}", "/a/{{x}}", false; "double braces are literal")]
+    #[test_case("/users/bob", "/users/{user}", false; "condition fails")]
+    fn captures_test(input: &str, policy: &str, expected: bool) {
+        let matcher = Captures::new().with_condition("user", |user, request: &Request<'_, ()>| {
+            user == request.identity()
+        });
+        let request = Request::new("alice", "some_operation", input).unwrap();
+
+        assert_eq!(expected, matcher.do_match(&request, input, policy));
+    }
+
+    #[test]
+    fn captures_values_test() {
+        let request = Request::new("alice", "some_operation", "/t/corp/u/alice").unwrap();
+
+        assert_eq!(
+            Some(vec![("tenant", "corp"), ("user", "alice")]),
+            Captures::<()>::new().captures(&request, request.resource(), "/t/{tenant}/u/{user}")
+        );
+    }
+
+    #[test_case("/home/alice", "/home/alice", true; "literal")]
+    #[test_case("/home/alice/notes.txt", "/home/*", true; "trailing star")]
+    #[test_case("/home/alice/notes.txt", "/home/*.txt", true; "star in the middle")]
+    #[test_case("/home/alice/notes.md", "/home/*.txt", false; "different suffix")]
+    #[test_case("/home/a.txt/b.md", "/home/*.txt", false; "suffix not at the end")]
+    #[test_case("/home/alice", "*", true; "star only")]
+    #[test_case("", "*", true; "star matches empty")]
+    #[test_case("/home", "/home/*", false; "missing separator")]
+    #[test_case("abcbcd", "a*bcd", true; "backtracking")]
+    fn glob_test(input: &str, policy: &str, expected: bool) {
+        let request = Request::new("some_identity", "some_operation", input).unwrap();
+
+        assert_eq!(expected, Glob.do_match(&request, input, policy));
+    }
 }
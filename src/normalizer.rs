@@ -0,0 +1,218 @@
+use std::borrow::Cow;
+
+use percent_encoding::percent_decode_str;
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+/// Trait to extend [`Policy`](`crate::Policy`) with value normalization.
+///
+/// [`PolicyBuilder`](`crate::PolicyBuilder`) applies the normalizer to policy values without
+/// variables at build time, and [`Policy`](`crate::Policy`) applies it to every
+/// [`Request`](`crate::Request`) value and to the result of variable substitution before
+/// matching. Every value is normalized once, so steps do not need to be idempotent.
+pub trait Normalizer {
+    /// Normalizes an identity value.
+    fn normalize_identity<'a>(&self, value: &'a str) -> Cow<'a, str>;
+
+    /// Normalizes an operation value.
+    fn normalize_operation<'a>(&self, value: &'a str) -> Cow<'a, str>;
+
+    /// Normalizes a resource value.
+    fn normalize_resource<'a>(&self, value: &'a str) -> Cow<'a, str>;
+}
+
+/// Default normalizer leaves all values unchanged.
+#[derive(Debug, Clone, Copy)]
+pub struct Default;
+
+impl Normalizer for Default {
+    fn normalize_identity<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(value)
+    }
+
+    fn normalize_operation<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(value)
+    }
+
+    fn normalize_resource<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(value)
+    }
+}
+
+/// A single normalization step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// Converts the value to lowercase.
+    CaseFold,
+    /// Converts the value to Unicode Normalization Form C.
+    Nfc,
+    /// Decodes `%XX` sequences. Invalid UTF-8 sequences are replaced with `U+FFFD`.
+    PercentDecode,
+    /// Removes trailing `/` characters. A value consisting only of slashes becomes `/`.
+    TrimTrailingSlash,
+}
+
+impl Step {
+    fn apply(self, value: Cow<'_, str>) -> Cow<'_, str> {
+        match value {
+            Cow::Borrowed(value) => self.apply_str(value),
+            Cow::Owned(value) => {
+                let changed = match self.apply_str(&value) {
+                    Cow::Borrowed(changed) if changed.len() == value.len() => None,
+                    changed => Some(changed.into_owned()),
+                };
+                Cow::Owned(changed.unwrap_or(value))
+            }
+        }
+    }
+
+    fn apply_str(self, value: &str) -> Cow<'_, str> {
+        match self {
+            Step::CaseFold => {
+                if value.chars().any(char::is_uppercase) {
+                    Cow::Owned(value.to_lowercase())
+                } else {
+                    Cow::Borrowed(value)
+                }
+            }
+            Step::Nfc => {
+                if is_nfc_quick(value.chars()) == IsNormalized::Yes {
+                    Cow::Borrowed(value)
+                } else {
+                    Cow::Owned(value.nfc().collect())
+                }
+            }
+            Step::PercentDecode => percent_decode_str(value).decode_utf8_lossy(),
+            Step::TrimTrailingSlash => {
+                let trimmed = value.trim_end_matches('/');
+                if trimmed.is_empty() {
+                    Cow::Borrowed(&value[..value.len().min(1)])
+                } else {
+                    Cow::Borrowed(trimmed)
+                }
+            }
+        }
+    }
+}
+
+/// Configurable normalizer that applies a sequence of [`Step`]s to each field.
+///
+/// Steps are applied in the order they were specified.
+///
+/// ```rust
+/// use allow_me::normalizer::{Pipeline, Step};
+///
+/// let normalizer = Pipeline::new()
+///     .identity(vec![Step::CaseFold])
+///     .resource(vec![Step::PercentDecode, Step::Nfc, Step::TrimTrailingSlash]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    identity: Vec<Step>,
+    operation: Vec<Step>,
+    resource: Vec<Step>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline with no steps.
+    pub fn new() -> Self {
+        Self {
+            identity: Vec::new(),
+            operation: Vec::new(),
+            resource: Vec::new(),
+        }
+    }
+
+    /// Specifies the steps applied to identities.
+    #[must_use]
+    pub fn identity(mut self, steps: impl IntoIterator<Item = Step>) -> Self {
+        self.identity = steps.into_iter().collect();
+        self
+    }
+
+    /// Specifies the steps applied to operations.
+    #[must_use]
+    pub fn operation(mut self, steps: impl IntoIterator<Item = Step>) -> Self {
+        self.operation = steps.into_iter().collect();
+        self
+    }
+
+    /// Specifies the steps applied to resources.
+    #[must_use]
+    pub fn resource(mut self, steps: impl IntoIterator<Item = Step>) -> Self {
+        self.resource = steps.into_iter().collect();
+        self
+    }
+}
+
+impl std::default::Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Normalizer for Pipeline {
+    fn normalize_identity<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        apply_steps(&self.identity, value)
+    }
+
+    fn normalize_operation<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        apply_steps(&self.operation, value)
+    }
+
+    fn normalize_resource<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        apply_steps(&self.resource, value)
+    }
+}
+
+fn apply_steps<'a>(steps: &[Step], value: &'a str) -> Cow<'a, str> {
+    steps
+        .iter()
+        .fold(Cow::Borrowed(value), |value, step| step.apply(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(Step::CaseFold, "Alice@Corp", "alice@corp"; "case fold")]
+    #[test_case(Step::Nfc, "cafe\u{301}", "caf\u{e9}"; "nfc")]
+    #[test_case(Step::PercentDecode, "/home/al%69ce/a%20b", "/home/alice/a b"; "percent decode")]
+    #[test_case(Step::PercentDecode, "100%", "100%"; "percent decode incomplete sequence")]
+    #[test_case(Step::TrimTrailingSlash, "/home/alice//", "/home/alice"; "trim trailing slash")]
+    #[test_case(Step::TrimTrailingSlash, "//", "/"; "trim trailing slash root")]
+    #[test_case(Step::TrimTrailingSlash, "", ""; "trim trailing slash empty")]
+    fn step_test(step: Step, input: &str, expected: &str) {
+        assert_eq!(expected, step.apply_str(input));
+    }
+
+    #[test]
+    fn unchanged_value_is_not_copied() {
+        let normalizer = Pipeline::new().resource(vec![
+            Step::PercentDecode,
+            Step::CaseFold,
+            Step::Nfc,
+            Step::TrimTrailingSlash,
+        ]);
+
+        assert!(matches!(
+            normalizer.normalize_resource("/home/alice"),
+            Cow::Borrowed("/home/alice")
+        ));
+    }
+
+    #[test]
+    fn steps_are_applied_per_field_in_order() {
+        let normalizer = Pipeline::new()
+            .identity(vec![Step::CaseFold])
+            .resource(vec![Step::PercentDecode, Step::TrimTrailingSlash]);
+
+        assert_eq!("alice", normalizer.normalize_identity("ALICE"));
+        assert_eq!("WRITE", normalizer.normalize_operation("WRITE"));
+        assert_eq!(
+            "/Home/Alice",
+            normalizer.normalize_resource("/Home/Alice%2F/")
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{Error, Request};
+use crate::{
+    filter::{self, Value},
+    Error, Expression, Field, Request, Segment, Template, Token, Tokenizer, Variable,
+};
 
 /// Trait to extend [`Policy`](`crate::Policy`) variable rules resolution.
 pub trait Substituter {
@@ -9,22 +12,94 @@ pub trait Substituter {
     fn visit_identity(
         &self,
         value: &str,
-        context: &Request<Self::Context>,
+        context: &Request<'_, Self::Context>,
     ) -> Result<String, Error>;
 
     /// This method is called by [`Policy`](`crate::Policy`) on every [`Request`] for every variable operation rule.
     fn visit_operation(
         &self,
         value: &str,
-        context: &Request<Self::Context>,
+        context: &Request<'_, Self::Context>,
     ) -> Result<String, Error>;
 
     /// This method is called by [`Policy`](`crate::Policy`) on every [`Request`] for every variable resource rule.
     fn visit_resource(
         &self,
         value: &str,
-        context: &Request<Self::Context>,
+        context: &Request<'_, Self::Context>,
     ) -> Result<String, Error>;
+
+    /// Renders a precompiled identity value into the buffer.
+    ///
+    /// [`Policy`](`crate::Policy`) calls it instead of [`Substituter::visit_identity`]
+    /// with a buffer reused across requests. The default implementation calls `visit_identity`.
+    fn render_identity(
+        &self,
+        template: &Template,
+        context: &Request<'_, Self::Context>,
+        buffer: &mut String,
+    ) -> Result<(), Error> {
+        buffer.push_str(&self.visit_identity(template.as_str(), context)?);
+        Ok(())
+    }
+
+    /// Renders a precompiled operation value into the buffer.
+    /// The default implementation calls [`Substituter::visit_operation`].
+    fn render_operation(
+        &self,
+        template: &Template,
+        context: &Request<'_, Self::Context>,
+        buffer: &mut String,
+    ) -> Result<(), Error> {
+        buffer.push_str(&self.visit_operation(template.as_str(), context)?);
+        Ok(())
+    }
+
+    /// Renders a precompiled resource value into the buffer.
+    /// The default implementation calls [`Substituter::visit_resource`].
+    fn render_resource(
+        &self,
+        template: &Template,
+        context: &Request<'_, Self::Context>,
+        buffer: &mut String,
+    ) -> Result<(), Error> {
+        buffer.push_str(&self.visit_resource(template.as_str(), context)?);
+        Ok(())
+    }
+
+    /// Returns `true` if rendering the template of the statement field may read the
+    /// request resource, e.g. `{{any}}` in resources of [`DefaultSubstituter`].
+    ///
+    /// Batch evaluation, e.g. [`Policy::evaluate_many`](`crate::Policy::evaluate_many`),
+    /// renders other templates once for requests that only differ in the resource.
+    /// The default implementation returns `true`, so templates are rendered for every request.
+    fn reads_resource(&self, _field: Field, _template: &Template) -> bool {
+        true
+    }
+
+    /// Returns `true` if rendering the template of the statement field may depend on anything
+    /// besides the identity, operation and resource of the request, e.g. its context or time.
+    ///
+    /// [`CachedPolicy`](`crate::CachedPolicy`) does not cache decisions of requests that
+    /// may match such templates. The default implementation returns `true`.
+    fn is_volatile(&self, _field: Field, _template: &Template) -> bool {
+        true
+    }
+
+    /// Returns the variables supported in values of the statement field, e.g. `{{identity}}`.
+    ///
+    /// [`PolicyBuilder`](`crate::PolicyBuilder`) uses it to report unknown variables.
+    /// `None` means that any variable is supported and the check is skipped.
+    fn variables(&self, _field: Field) -> Option<&[&str]> {
+        None
+    }
+
+    /// Returns the names of the supported variable filters, e.g. `lower`.
+    ///
+    /// `None` means that any filter is supported and the check is skipped.
+    fn filters(&self) -> Option<Vec<&str>> {
+        None
+    }
 }
 
 pub(crate) const ANY_VAR: &str = "{{any}}";
@@ -35,7 +110,9 @@ pub(crate) const OPERATION_VAR: &str = "{{operation}}";
 /// * `any` - replaced by input value from the Request.
 /// * `identity` - replaced by identity value from the Request.
 /// * `operation` - replaced by operation value from the Request.
-#[derive(Debug)]
+///
+/// Variable values can be transformed by [filters](`crate::filter`), e.g. `{{identity|lower}}`.
+#[derive(Debug, Default)]
 pub struct DefaultSubstituter;
 
 impl Substituter for DefaultSubstituter {
@@ -44,98 +121,188 @@ impl Substituter for DefaultSubstituter {
     fn visit_identity(
         &self,
         value: &str,
-        context: &Request<Self::Context>,
+        context: &Request<'_, Self::Context>,
     ) -> Result<String, Error> {
-        Ok(replace_identity(value, context))
+        substitute(value, |name| identity_lookup(name, context))
     }
 
     fn visit_operation(
         &self,
         value: &str,
-        context: &Request<Self::Context>,
+        context: &Request<'_, Self::Context>,
     ) -> Result<String, Error> {
-        Ok(replace_operation(value, context))
+        substitute(value, |name| operation_lookup(name, context))
     }
 
     fn visit_resource(
         &self,
         value: &str,
-        context: &Request<Self::Context>,
+        context: &Request<'_, Self::Context>,
     ) -> Result<String, Error> {
-        Ok(replace_resource(value, context))
+        substitute(value, |name| resource_lookup(name, context))
     }
+
+    fn render_identity(
+        &self,
+        template: &Template,
+        context: &Request<'_, Self::Context>,
+        buffer: &mut String,
+    ) -> Result<(), Error> {
+        render(template, |name| identity_lookup(name, context), buffer)
+    }
+
+    fn render_operation(
+        &self,
+        template: &Template,
+        context: &Request<'_, Self::Context>,
+        buffer: &mut String,
+    ) -> Result<(), Error> {
+        render(template, |name| operation_lookup(name, context), buffer)
+    }
+
+    fn render_resource(
+        &self,
+        template: &Template,
+        context: &Request<'_, Self::Context>,
+        buffer: &mut String,
+    ) -> Result<(), Error> {
+        render(template, |name| resource_lookup(name, context), buffer)
+    }
+
+    fn variables(&self, field: Field) -> Option<&[&str]> {
+        match field {
+            Field::Identities => Some(&[ANY_VAR, IDENTITY_VAR]),
+            Field::Operations | Field::Resources => Some(&[ANY_VAR, IDENTITY_VAR, OPERATION_VAR]),
+            _ => Some(&[]),
+        }
+    }
+
+    fn filters(&self) -> Option<Vec<&str>> {
+        Some(filter::builtin_names().collect())
+    }
+
+    fn reads_resource(&self, field: Field, template: &Template) -> bool {
+        field == Field::Resources
+            && template.segments().iter().any(|segment| match segment {
+                Segment::Variable(variable) => is_supported(&[ANY_VAR], variable.name()),
+                Segment::Literal(_) => false,
+            })
+    }
+
+    // variables are rendered from the request, and filters are expected to only depend
+    // on their input.
+    fn is_volatile(&self, _field: Field, _template: &Template) -> bool {
+        false
+    }
+}
+
+/// Renders the template into the buffer using the provided lookup.
+/// Unknown variables, and variables that do not evaluate to a string, are left in place.
+fn render<'a>(
+    template: &Template,
+    lookup: impl Fn(&str) -> Option<&'a str>,
+    buffer: &mut String,
+) -> Result<(), Error> {
+    for segment in template.segments() {
+        match segment {
+            Segment::Literal(literal) => buffer.push_str(literal),
+            // plain variables are rendered without allocations.
+            Segment::Variable(variable) if variable.filters().next().is_none() => {
+                buffer.push_str(lookup(variable.name()).unwrap_or(variable.as_str()));
+            }
+            Segment::Variable(variable) => match evaluate(template, variable, &lookup)? {
+                Value::Str(substitution) => buffer.push_str(&substitution),
+                Value::Missing | Value::List(_) => buffer.push_str(variable.as_str()),
+            },
+        }
+    }
+    Ok(())
+}
+
+fn substitute<'a>(value: &str, lookup: impl Fn(&str) -> Option<&'a str>) -> Result<String, Error> {
+    let mut result = String::with_capacity(value.len());
+    render(&Template::parse(value)?, lookup, &mut result)?;
+    Ok(result)
+}
+
+fn evaluate<'a>(
+    template: &Template,
+    variable: &Variable,
+    lookup: impl Fn(&str) -> Option<&'a str>,
+) -> Result<Value, Error> {
+    let mut value = lookup(variable.name()).map_or(Value::Missing, |substitution| {
+        Value::Str(substitution.to_string())
+    });
+    for (name, argument) in variable.filters() {
+        match template.filter(name) {
+            Some(filter) => value = filter.apply(value, argument)?,
+            None => return Ok(Value::Missing),
+        }
+    }
+    Ok(value)
 }
 
-fn replace_identity<RC>(value: &str, context: &Request<RC>) -> String {
-    let mut result = value.to_owned();
-    for variable in VariableIter::new(value) {
-        result = match variable {
-            ANY_VAR | IDENTITY_VAR => replace(&result, variable, context.identity()),
-            _ => result,
-        };
+fn identity_lookup<'a>(name: &str, context: &'a Request<'_, ()>) -> Option<&'a str> {
+    match name {
+        "any" | "identity" => Some(context.identity()),
+        _ => None,
     }
-    result
 }
 
-fn replace_operation<RC>(value: &str, context: &Request<RC>) -> String {
-    let mut result = value.to_owned();
-    for variable in VariableIter::new(value) {
-        result = match variable {
-            ANY_VAR | OPERATION_VAR => replace(&result, variable, context.operation()),
-            IDENTITY_VAR => replace(&result, variable, context.identity()),
-            _ => result,
-        };
+fn operation_lookup<'a>(name: &str, context: &'a Request<'_, ()>) -> Option<&'a str> {
+    match name {
+        "any" | "operation" => Some(context.operation()),
+        "identity" => Some(context.identity()),
+        _ => None,
     }
-    result
 }
 
-fn replace_resource<RC>(value: &str, context: &Request<RC>) -> String {
-    let mut result = value.to_owned();
-    for variable in VariableIter::new(value) {
-        result = match variable {
-            ANY_VAR => replace(&result, variable, context.resource()),
-            IDENTITY_VAR => replace(&result, variable, context.identity()),
-            OPERATION_VAR => replace(&result, variable, context.operation()),
-            _ => result,
-        };
+fn resource_lookup<'a>(name: &str, context: &'a Request<'_, ()>) -> Option<&'a str> {
+    match name {
+        "any" => Some(context.resource()),
+        "identity" => Some(context.identity()),
+        "operation" => Some(context.operation()),
+        _ => None,
     }
-    result
 }
 
-fn replace(value: &str, variable: &str, substitution: &str) -> String {
-    value.replace(variable, substitution)
+/// Checks if the variable name, e.g. `identity`, is in the list of supported variables.
+pub(crate) fn is_supported(variables: &[&str], name: &str) -> bool {
+    variables
+        .iter()
+        .any(|variable| Expression::parse(variable).name() == name)
 }
 
 /// A simple iterator that returns all occurrences
 /// of variable substrings like `{{var_name}}` in the
-/// provided string value. Can be used in your custom
-/// [`Substituter`] implementation.
+/// provided string value. Stops at the first malformed variable.
+#[deprecated(note = "use `Tokenizer`, which also handles escapes and reports malformed variables")]
 #[derive(Debug)]
 pub struct VariableIter<'a> {
-    value: &'a str,
-    index: usize,
+    tokens: Tokenizer<'a>,
 }
 
+#[allow(deprecated)]
 impl<'a> VariableIter<'a> {
     pub fn new(value: &'a str) -> Self {
-        Self { value, index: 0 }
+        Self {
+            tokens: Tokenizer::new(value),
+        }
     }
 }
 
+#[allow(deprecated)]
 impl<'a> Iterator for VariableIter<'a> {
     type Item = &'a str;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let value = &self.value[self.index..];
-        if let Some(start) = value.find("{{") {
-            if let Some(end) = value.find("}}") {
-                if start < end {
-                    self.index = self.index + end + 2;
-                    return Some(&value[start..end + 2]);
-                }
+        loop {
+            match self.tokens.next()? {
+                Ok(Token::Variable(variable)) => return Some(variable),
+                Ok(Token::Literal(_)) => {}
+                Err(_) => return None,
             }
         }
-        None
     }
 }
 
@@ -257,10 +424,40 @@ mod tests {
         );
     }
 
+    #[test_case("/home/{{identity}}/{{any}}", "/home/alice@corp/files"; "plain variables")]
+    #[test_case("/home/{{identity|split:@|first}}/", "/home/alice/"; "filters")]
+    #[test_case("/home/{{identity | split: @ | first}}/", "/home/alice/"; "spaced filters")]
+    #[test_case(r"/raw/\{{any}}/{{role}}", "/raw/{{any}}/{{role}}"; "escaped and unknown")]
+    fn render_resource_test(input: &str, expected: &str) {
+        let request = Request::new("alice@corp", "read", "files").unwrap();
+        let mut buffer = "previous/".to_string();
+
+        DefaultSubstituter
+            .render_resource(&Template::parse(input).unwrap(), &request, &mut buffer)
+            .unwrap();
+
+        assert_eq!(format!("previous/{expected}"), buffer);
+    }
+
+    #[allow(deprecated)]
+    #[test]
+    fn variable_iter_test() {
+        let variables = VariableIter::new("a}}b{{c}}/{{d}}/{{e").collect::<Vec<_>>();
+
+        assert_eq!(vec!["{{c}}", "{{d}}"], variables);
+    }
+
     proptest! {
+        #[allow(deprecated)]
         #[test]
         fn iterator_does_not_crash(value in "[a-z\\{\\}]+") {
             let _ = VariableIter::new(&value).collect::<Vec<_>>();
         }
+
+        #[test]
+        fn substitution_does_not_crash(value in "[a-z\\\\\\{\\}]+") {
+            let request = Request::new("identity", "operation", "resource").unwrap();
+            let _ = DefaultSubstituter.visit_resource(&value, &request);
+        }
     }
 }
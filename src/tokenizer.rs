@@ -0,0 +1,466 @@
+use std::{
+    borrow::{Borrow, Cow},
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use thiserror::Error;
+
+use crate::filter::{self, Filter, Filters};
+
+/// A segment of a policy value produced by [`Tokenizer`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Token<'a> {
+    /// Literal text. Escaped braces are returned without the backslash.
+    Literal(&'a str),
+    /// A variable including its braces, e.g. `{{identity}}`.
+    Variable(&'a str),
+}
+
+/// An error in the variable syntax of a policy value.
+///
+/// Positions are byte offsets in the value.
+#[derive(Debug, Copy, Clone, PartialEq, Error)]
+pub enum TokenError {
+    #[error("Variable at position {0} is not closed with }}}}")]
+    Unclosed(usize),
+
+    #[error("Variable at position {0} has an empty or invalid name")]
+    InvalidName(usize),
+}
+
+/// Splits a policy value into literal and variable tokens.
+///
+/// Variables are enclosed in double braces, e.g. `{{identity}}`. Literal opening braces
+/// are escaped with a backslash: `\{{`. Closing braces outside of a variable are literal.
+/// Malformed variables produce a [`TokenError`], after which the iteration stops.
+///
+/// ```rust
+/// use allow_me::{Token, Tokenizer};
+///
+/// let tokens = Tokenizer::new(r"/home/{{identity}}/\{{raw}}")
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+///
+/// assert_eq!(
+///     vec![
+///         Token::Literal("/home/"),
+///         Token::Variable("{{identity}}"),
+///         Token::Literal("/"),
+///         Token::Literal("{{"),
+///         Token::Literal("raw}}"),
+///     ],
+///     tokens
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Tokenizer<'a> {
+    value: &'a str,
+    position: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(value: &'a str) -> Self {
+        Self { value, position: 0 }
+    }
+
+    fn fail(&mut self, error: TokenError) -> Result<Token<'a>, TokenError> {
+        self.position = self.value.len();
+        Err(error)
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<Token<'a>, TokenError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.position;
+        let rest = &self.value[start..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        if rest.starts_with(r"\{{") {
+            self.position += 3;
+            return Some(Ok(Token::Literal(&rest[1..3])));
+        }
+
+        if rest.starts_with("{{") {
+            let Some(close) = rest.find("}}") else {
+                return Some(self.fail(TokenError::Unclosed(start)));
+            };
+            let expression = &rest[2..close];
+            if expression.contains(['{', '}']) || !Expression::parse(&rest[..close + 2]).is_valid()
+            {
+                return Some(self.fail(TokenError::InvalidName(start)));
+            }
+            self.position += close + 2;
+            return Some(Ok(Token::Variable(&rest[..close + 2])));
+        }
+
+        // the literal ends at the next variable or escape.
+        let end = (1..rest.len())
+            .find(|i| {
+                let tail = &rest.as_bytes()[*i..];
+                tail.starts_with(b"{{") || tail.starts_with(br"\{{")
+            })
+            .unwrap_or(rest.len());
+        self.position += end;
+        Some(Ok(Token::Literal(&rest[..end])))
+    }
+}
+
+/// A variable with the filters applied to its value, e.g. `{{identity|split:@|first}}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression<'a> {
+    name: &'a str,
+    filters: Vec<(&'a str, Option<&'a str>)>,
+}
+
+impl<'a> Expression<'a> {
+    /// Parses the text of a [`Token::Variable`].
+    ///
+    /// ```rust
+    /// use allow_me::Expression;
+    ///
+    /// let expression = Expression::parse("{{identity|split:@|first}}");
+    ///
+    /// assert_eq!("identity", expression.name());
+    /// assert_eq!(
+    ///     vec![("split", Some("@")), ("first", None)],
+    ///     expression.filters().collect::<Vec<_>>()
+    /// );
+    /// ```
+    pub fn parse(variable: &'a str) -> Self {
+        let inner = variable
+            .strip_prefix("{{")
+            .and_then(|v| v.strip_suffix("}}"))
+            .unwrap_or(variable);
+        let mut parts = inner.split('|');
+        let name = parts.next().unwrap_or_default().trim();
+        let filters = parts
+            .map(|filter| match filter.split_once(':') {
+                Some((name, argument)) => (name.trim(), Some(argument.trim())),
+                None => (filter.trim(), None),
+            })
+            .collect();
+        Self { name, filters }
+    }
+
+    /// The variable name, e.g. `identity`.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The filter names with their arguments, in the order they are applied.
+    pub fn filters(&self) -> impl Iterator<Item = (&'a str, Option<&'a str>)> + '_ {
+        self.filters.iter().copied()
+    }
+
+    /// Checks if the expression has a filter with the name.
+    pub fn has_filter(&self, name: &str) -> bool {
+        self.filters.iter().any(|(filter, _)| *filter == name)
+    }
+
+    fn is_valid(&self) -> bool {
+        !self.name.is_empty() && self.filters.iter().all(|(name, _)| !name.is_empty())
+    }
+}
+
+/// A policy value parsed once into literal and variable segments.
+///
+/// [`PolicyBuilder`](`crate::PolicyBuilder`) compiles the values of variable rules into templates,
+/// so that a [`Substituter`](`crate::Substituter`) does not parse them on every request.
+/// Templates are compared by their source value.
+///
+/// ```rust
+/// use allow_me::{Segment, Template};
+///
+/// let template = Template::parse(r"/home/{{identity|lower}}/\{{raw}}").unwrap();
+///
+/// assert_eq!(r"/home/{{identity|lower}}/\{{raw}}", template.as_str());
+/// assert_matches::assert_matches!(
+///     template.segments(),
+///     [Segment::Literal(home), Segment::Variable(identity), Segment::Literal(raw)]
+///         if home == "/home/" && identity.name() == "identity" && raw == "/{{raw}}"
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+    /// Custom filters of the policy, if any.
+    filters: Option<Arc<Filters>>,
+}
+
+/// A segment of a [`Template`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// Literal text with escapes resolved.
+    Literal(String),
+    /// A variable with its filters.
+    Variable(Variable),
+}
+
+/// A variable of a [`Template`]. The owned counterpart of [`Expression`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    source: String,
+    name: String,
+    filters: Vec<(String, Option<String>)>,
+}
+
+impl Template {
+    /// Parses the value into segments. Adjacent literals are merged.
+    pub fn parse(value: &str) -> Result<Self, TokenError> {
+        let mut segments = vec![];
+        for token in Tokenizer::new(value) {
+            match (token?, segments.last_mut()) {
+                (Token::Literal(literal), Some(Segment::Literal(last))) => last.push_str(literal),
+                (Token::Literal(literal), _) => {
+                    segments.push(Segment::Literal(literal.to_string()));
+                }
+                (Token::Variable(variable), _) => {
+                    segments.push(Segment::Variable(Variable::new(variable)));
+                }
+            }
+        }
+        Ok(Self {
+            source: value.to_string(),
+            segments,
+            filters: None,
+        })
+    }
+
+    /// Applies the custom filters, in addition to the built-in ones, to the variables.
+    pub(crate) fn with_filters(mut self, filters: &Arc<Filters>) -> Self {
+        if !filters.is_empty() {
+            self.filters = Some(Arc::clone(filters));
+        }
+        self
+    }
+
+    /// Returns the filter with the name that applies to the variables.
+    pub(crate) fn filter(&self, name: &str) -> Option<&(dyn Filter + Send + Sync)> {
+        match &self.filters {
+            Some(filters) => filters.get(name),
+            None => filter::builtin(name),
+        }
+    }
+
+    /// The source value, e.g. `/home/{{identity}}/`.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Heap bytes used by the template.
+    pub(crate) fn heap_size(&self) -> usize {
+        self.source.capacity()
+            + self.segments.capacity() * std::mem::size_of::<Segment>()
+            + self
+                .segments
+                .iter()
+                .map(|segment| match segment {
+                    Segment::Literal(literal) => literal.capacity(),
+                    Segment::Variable(variable) => {
+                        variable.source.capacity()
+                            + variable.name.capacity()
+                            + variable.filters.capacity()
+                                * std::mem::size_of::<(String, Option<String>)>()
+                            + variable
+                                .filters
+                                .iter()
+                                .map(|(name, argument)| {
+                                    name.capacity() + argument.as_ref().map_or(0, String::capacity)
+                                })
+                                .sum::<usize>()
+                    }
+                })
+                .sum::<usize>()
+    }
+}
+
+impl PartialEq for Template {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Template {}
+
+impl PartialOrd for Template {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Template {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.source.cmp(&other.source)
+    }
+}
+
+impl Hash for Template {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.source.hash(state);
+    }
+}
+
+impl Borrow<str> for Template {
+    fn borrow(&self) -> &str {
+        &self.source
+    }
+}
+
+impl Variable {
+    fn new(variable: &str) -> Self {
+        let expression = Expression::parse(variable);
+        Self {
+            source: variable.to_string(),
+            name: expression.name().to_string(),
+            filters: expression
+                .filters()
+                .map(|(name, argument)| (name.to_string(), argument.map(ToString::to_string)))
+                .collect(),
+        }
+    }
+
+    /// The variable including its braces, e.g. `{{identity|lower}}`.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// The variable name, e.g. `identity`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The filter names with their arguments, in the order they are applied.
+    pub fn filters(&self) -> impl Iterator<Item = (&str, Option<&str>)> + '_ {
+        self.filters
+            .iter()
+            .map(|(name, argument)| (name.as_str(), argument.as_deref()))
+    }
+}
+
+/// Returns the literal value with escapes resolved, or `None` if the value has variables.
+///
+/// Malformed values are returned as is.
+pub(crate) fn literal(value: &str) -> Option<Cow<'_, str>> {
+    let mut result = Cow::Borrowed("");
+    for token in Tokenizer::new(value) {
+        match token {
+            Ok(Token::Literal(literal)) if result.is_empty() => result = Cow::Borrowed(literal),
+            Ok(Token::Literal(literal)) => result.to_mut().push_str(literal),
+            Ok(Token::Variable(_)) => return None,
+            Err(_) => return Some(Cow::Borrowed(value)),
+        }
+    }
+    Some(result)
+}
+
+/// Escapes the opening braces of a literal value, so that it has no variables.
+pub(crate) fn escape(literal: &str) -> Cow<'_, str> {
+    if literal.contains("{{") {
+        Cow::Owned(literal.replace("{{", r"\{{"))
+    } else {
+        Cow::Borrowed(literal)
+    }
+}
+
+/// Checks that all variables of the value are well-formed.
+pub(crate) fn validate(value: &str) -> Result<(), TokenError> {
+    Tokenizer::new(value).try_for_each(|token| token.map(|_| ()))
+}
+
+/// Returns all variables of the value. Stops at the first malformed variable.
+pub(crate) fn variables(value: &str) -> impl Iterator<Item = &str> {
+    Tokenizer::new(value)
+        .map_while(|token| match token {
+            Ok(Token::Variable(variable)) => Some(Some(variable)),
+            Ok(Token::Literal(_)) => Some(None),
+            Err(_) => None,
+        })
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("", &[]; "empty")]
+    #[test_case("resource", &[Token::Literal("resource")]; "literal")]
+    #[test_case("{{any}}", &[Token::Variable("{{any}}")]; "variable")]
+    #[test_case("a{{b}}c", &[Token::Literal("a"), Token::Variable("{{b}}"), Token::Literal("c")]; "mixed")]
+    #[test_case("{{a}}{{b}}", &[Token::Variable("{{a}}"), Token::Variable("{{b}}")]; "adjacent variables")]
+    #[test_case("/{{a|split:@|first}}/", &[Token::Literal("/"), Token::Variable("{{a|split:@|first}}"), Token::Literal("/")]; "filters")]
+    #[test_case(r"\{{a}}", &[Token::Literal("{{"), Token::Literal("a}}")]; "escaped")]
+    #[test_case("a}}b{{c}}", &[Token::Literal("a}}b"), Token::Variable("{{c}}")]; "closing braces before variable")]
+    #[test_case(r"a\b{c}", &[Token::Literal(r"a\b{c}")]; "single braces and backslash")]
+    #[test_case("{{a}}}", &[Token::Variable("{{a}}"), Token::Literal("}")]; "trailing brace")]
+    #[test_case(r"{{a}}\{{", &[Token::Variable("{{a}}"), Token::Literal("{{")]; "escape after variable")]
+    #[test_case("ключ/{{identity}}", &[Token::Literal("ключ/"), Token::Variable("{{identity}}")]; "unicode")]
+    fn tokenize_test(value: &str, expected: &[Token<'_>]) {
+        let tokens = Tokenizer::new(value)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(expected, tokens.as_slice());
+    }
+
+    #[test_case("a/{{b", TokenError::Unclosed(2); "unclosed")]
+    #[test_case("{{}}", TokenError::InvalidName(0); "empty name")]
+    #[test_case("x{{a{{b}}", TokenError::InvalidName(1); "nested")]
+    #[test_case("{{{a}}", TokenError::InvalidName(0); "triple braces")]
+    #[test_case("{{a|}}", TokenError::InvalidName(0); "empty filter")]
+    #[test_case("{{ |lower}}", TokenError::InvalidName(0); "filter without variable")]
+    fn malformed_test(value: &str, expected: TokenError) {
+        assert_eq!(Err(expected), validate(value));
+    }
+
+    #[test]
+    fn template_test() {
+        let template = Template::parse(r"a\{{b}}{{c|split:@|first}}d").unwrap();
+
+        assert_eq!(r"a\{{b}}{{c|split:@|first}}d", template.as_str());
+        assert_eq!(3, template.segments().len());
+        assert_eq!(
+            Segment::Literal("a{{b}}".to_string()),
+            template.segments()[0]
+        );
+        let variable = assert_matches!(&template.segments()[1], Segment::Variable(v) => v);
+        assert_eq!("{{c|split:@|first}}", variable.as_str());
+        assert_eq!("c", variable.name());
+        assert_eq!(
+            vec![("split", Some("@")), ("first", None)],
+            variable.filters().collect::<Vec<_>>()
+        );
+        assert_eq!(Segment::Literal("d".to_string()), template.segments()[2]);
+        assert_eq!(Err(TokenError::Unclosed(1)), Template::parse("a{{b"));
+    }
+
+    #[test_case("a", "a"; "no braces")]
+    #[test_case("{{identity}}", r"\{{identity}}"; "variable")]
+    #[test_case("{{{a}}", r"\{{{a}}"; "triple braces")]
+    #[test_case(r"a\{{", r"a\\{{"; "backslash")]
+    fn escape_test(literal: &str, expected: &str) {
+        assert_eq!(expected, escape(literal));
+        assert_eq!(Some(Cow::Borrowed(literal)), super::literal(expected));
+    }
+
+    #[test_case("resource", Some("resource"); "literal")]
+    #[test_case(r"\{{any}}", Some("{{any}}"); "escaped")]
+    #[test_case("{{any}}", None; "variable")]
+    #[test_case(r"\{{a}}/{{b}}", None; "escaped and variable")]
+    fn literal_test(value: &str, expected: Option<&str>) {
+        assert_eq!(expected, literal(value).as_deref());
+    }
+}
@@ -1,3 +1,9 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
 use thiserror::Error;
 
 use crate::{PolicyDefinition, Statement};
@@ -12,6 +18,64 @@ pub trait PolicyValidator {
     ///
     /// If a policy definitions fails the validation, the error is returned.
     fn validate(&self, definition: &PolicyDefinition) -> Result<(), Self::Error>;
+
+    /// Returns problems that do not fail the validation.
+    ///
+    /// The warnings are available from [`Policy::warnings`](`crate::Policy::warnings`).
+    fn warnings(&self, _definition: &PolicyDefinition) -> Vec<Diagnostic> {
+        Vec::new()
+    }
+}
+
+/// Combines two validators. Diagnostics of both validators are reported.
+///
+/// ```rust
+/// use allow_me::{matcher::MatchSemantics, DefaultValidator, PolicyAnalyzer, PolicyBuilder};
+///
+/// let builder = PolicyBuilder::from_json(r#"{ "statements": [ ] }"#).with_validator((
+///     DefaultValidator,
+///     PolicyAnalyzer::new(MatchSemantics::Equality),
+/// ));
+/// ```
+impl<A, B> PolicyValidator for (A, B)
+where
+    A: PolicyValidator<Error = ValidatorError>,
+    B: PolicyValidator<Error = ValidatorError>,
+{
+    type Error = ValidatorError;
+
+    fn validate(&self, definition: &PolicyDefinition) -> Result<(), Self::Error> {
+        let mut errors = vec![];
+        for result in [self.0.validate(definition), self.1.validate(definition)] {
+            match result {
+                Err(ValidatorError::ValidationSummary(diagnostics)) => errors.extend(diagnostics),
+                Err(e) => return Err(e),
+                Ok(()) => {}
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(ValidatorError::ValidationSummary(errors));
+        }
+        Ok(())
+    }
+
+    fn warnings(&self, definition: &PolicyDefinition) -> Vec<Diagnostic> {
+        let mut warnings = self.0.warnings(definition);
+        warnings.extend(self.1.warnings(definition));
+        warnings
+    }
+}
+
+/// Defines how a validator reports a problem it found.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Severity {
+    /// The problem is not reported.
+    Ignore,
+    /// The problem is reported as a warning and the policy is built.
+    Warn,
+    /// The problem fails the validation.
+    Fail,
 }
 
 /// Provides basic validation that policy definition elements are not empty.
@@ -25,7 +89,7 @@ impl PolicyValidator for DefaultValidator {
         let errors = definition
             .statements()
             .iter()
-            .flat_map(|statement| visit_statement(statement))
+            .flat_map(visit_statement)
             .collect::<Vec<_>>();
 
         if !errors.is_empty() {
@@ -35,19 +99,329 @@ impl PolicyValidator for DefaultValidator {
     }
 }
 
-fn visit_statement(statement: &Statement) -> Vec<String> {
+fn visit_statement(statement: &Statement) -> Vec<Diagnostic> {
     let mut result = vec![];
     if statement.identities().is_empty() {
-        result.push("Identities list must not be empty".into());
+        result.push(Diagnostic::new(
+            statement,
+            Field::Identities,
+            "Identities list must not be empty",
+        ));
     }
     if statement.operations().is_empty() {
-        result.push("Operations list must not be empty".into());
+        result.push(Diagnostic::new(
+            statement,
+            Field::Operations,
+            "Operations list must not be empty",
+        ));
+    }
+    result
+}
+
+/// Reports empty and duplicate statement ids. Checked for every policy, regardless
+/// of the validator, because statements are updated by their ids.
+///
+/// The ids of unchanged statements are taken first, so a duplicate is reported on
+/// the changed statement, at any position.
+pub(crate) fn visit_sids(
+    definition: &PolicyDefinition,
+    changed: &BTreeSet<usize>,
+) -> Vec<Diagnostic> {
+    let (changed, unchanged): (Vec<_>, Vec<_>) = definition
+        .statements()
+        .iter()
+        .partition(|statement| changed.contains(&statement.order()));
+
+    let mut result = vec![];
+    let mut sids = BTreeMap::new();
+    for statement in unchanged.into_iter().chain(changed) {
+        if let Some(sid) = statement.sid() {
+            if sid.is_empty() {
+                result.push(Diagnostic::new(
+                    statement,
+                    Field::Sid,
+                    "Statement id must not be empty",
+                ));
+            } else if let Some(first) = sids.get(sid) {
+                result.push(Diagnostic::new(
+                    statement,
+                    Field::Sid,
+                    format!("Statement id is already used by statement #{first}"),
+                ));
+            } else {
+                sids.insert(sid, statement.order());
+            }
+        }
     }
     result
 }
 
 #[derive(Debug, Error)]
 pub enum ValidatorError {
-    #[error("An error occurred validating policy definition: {0:?}.")]
-    ValidationSummary(Vec<String>),
+    #[error("{}", summary(.0))]
+    ValidationSummary(Vec<Diagnostic>),
+    /// The error of a custom [`PolicyValidator`], which is kept as its message.
+    #[error("{0}")]
+    Custom(String),
+}
+
+fn summary(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// A field of a [`Statement`] that a [`Diagnostic`] refers to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Field {
+    Statement,
+    Sid,
+    Description,
+    Effect,
+    Identities,
+    Operations,
+    Resources,
+}
+
+impl Field {
+    /// Returns the name of the field in the policy definition.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Field::Statement => "",
+            Field::Sid => "sid",
+            Field::Description => "description",
+            Field::Effect => "effect",
+            Field::Identities => "identities",
+            Field::Operations => "operations",
+            Field::Resources => "resources",
+        }
+    }
+}
+
+/// Describes a single problem found in a policy definition, and where it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    statement: usize,
+    sid: Option<String>,
+    description: String,
+    field: Field,
+    value: Option<String>,
+    path: String,
+    source: Option<PathBuf>,
+    line: Option<usize>,
+    code: Option<&'static str>,
+    message: String,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic for a field of the statement.
+    pub fn new(statement: &Statement, field: Field, message: impl Into<String>) -> Self {
+        // the path points into the file the statement was read from, if any.
+        let mut path = format!("/statements/{}", statement.index());
+        if field != Field::Statement {
+            path.push('/');
+            path.push_str(field.as_str());
+        }
+
+        Self {
+            statement: statement.order(),
+            sid: statement.sid().map(ToString::to_string),
+            description: statement.description().to_string(),
+            field,
+            value: None,
+            path,
+            source: statement.source().map(Path::to_path_buf),
+            line: statement.line(),
+            code: None,
+            message: message.into(),
+        }
+    }
+
+    /// Specifies the offending value and its position in the field list.
+    #[must_use]
+    pub fn with_value(mut self, position: usize, value: impl Into<String>) -> Self {
+        self.path.push('/');
+        self.path.push_str(&position.to_string());
+        self.value = Some(value.into());
+        self
+    }
+
+    /// Specifies the identifier of the check that reported the problem.
+    #[must_use]
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// The index of the statement in the policy definition.
+    pub fn statement(&self) -> usize {
+        self.statement
+    }
+
+    /// The id of the statement, if specified.
+    pub fn sid(&self) -> Option<&str> {
+        self.sid.as_deref()
+    }
+
+    /// The description of the statement.
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// The field of the statement that has the problem, or [`Field::Statement`] if the
+    /// problem concerns the whole statement.
+    pub fn field(&self) -> Field {
+        self.field
+    }
+
+    /// The offending value, if the problem is caused by a specific value.
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// The JSON pointer to the offending element, e.g. `/statements/3/identities/1`.
+    /// It points into the [source](`Diagnostic::source`) file of the statement, if any.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The file of the statement, if the definition was read from a directory.
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    /// The line where the statement starts, if the definition was parsed from json.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// The identifier of the check that reported the problem, e.g. a lint id.
+    pub fn code(&self) -> Option<&str> {
+        self.code
+    }
+
+    /// The description of the problem.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "statement #{}", self.statement)?;
+        if let Some(sid) = &self.sid {
+            write!(f, " [{sid}]")?;
+        }
+        if !self.description.is_empty() {
+            write!(f, " ({:?})", self.description)?;
+        }
+        if let Some(source) = &self.source {
+            write!(f, " in {}", source.display())?;
+        }
+        if let Some(line) = self.line {
+            write!(f, " at line {line}")?;
+        }
+        write!(f, ", {}: ", self.path)?;
+        if let Some(code) = self.code {
+            write!(f, "[{code}] ")?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(value) = &self.value {
+            write!(f, " (value: {value:?})")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    #[test]
+    fn validation_diagnostics_test() {
+        let json = r#"{
+            "statements": [
+                {
+                    "effect": "allow",
+                    "identities": [
+                        "actor_a"
+                    ],
+                    "operations": [
+                        "write"
+                    ]
+                },
+                {
+                    "description": "Broken statement",
+                    "effect": "allow",
+                    "identities": [ ],
+                    "operations": [ ]
+                }
+            ]
+        }"#;
+
+        let definition = PolicyDefinition::from_json(json).unwrap();
+
+        let errors = assert_matches!(
+            DefaultValidator.validate(&definition),
+            Err(ValidatorError::ValidationSummary(errors)) => errors
+        );
+
+        assert_eq!(2, errors.len());
+
+        assert_eq!(1, errors[0].statement());
+        assert_eq!("Broken statement", errors[0].description());
+        assert_eq!(Field::Identities, errors[0].field());
+        assert_eq!(None, errors[0].value());
+        assert_eq!("/statements/1/identities", errors[0].path());
+        assert_eq!(Some(12), errors[0].line());
+
+        assert_eq!(Field::Operations, errors[1].field());
+        assert_eq!("/statements/1/operations", errors[1].path());
+        assert_eq!(
+            "statement #1 (\"Broken statement\") at line 12, /statements/1/operations: \
+             Operations list must not be empty",
+            errors[1].to_string()
+        );
+    }
+
+    #[test]
+    fn duplicate_sid_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| s.sid("first").identity("actor_a").operation("read"))
+            .statement(|s| s.sid("second").identity("actor_a").operation("write"))
+            .statement(|s| s.sid("first").identity("actor_b").operation("read"))
+            .build();
+
+        let errors = visit_sids(&definition, &BTreeSet::new());
+
+        assert_eq!(1, errors.len());
+        assert_eq!(2, errors[0].statement());
+        assert_eq!(Some("first"), errors[0].sid());
+        assert_eq!("/statements/2/sid", errors[0].path());
+        assert_eq!(
+            "statement #2 [first], /statements/2/sid: Statement id is already used by statement #0",
+            errors[0].to_string()
+        );
+    }
+
+    #[test]
+    fn duplicate_sid_of_changed_statement_test() {
+        let definition = PolicyDefinition::builder()
+            .statement(|s| s.sid("first").identity("actor_a").operation("read"))
+            .statement(|s| s.sid("first").identity("actor_b").operation("read"))
+            .build();
+
+        let errors = visit_sids(&definition, &BTreeSet::from([0]));
+
+        assert_eq!(1, errors.len());
+        assert_eq!(0, errors[0].statement());
+        assert_eq!(
+            "statement #0 [first], /statements/0/sid: Statement id is already used by statement #1",
+            errors[0].to_string()
+        );
+    }
 }
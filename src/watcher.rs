@@ -0,0 +1,418 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt, fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    matcher, normalizer, DefaultSubstituter, Error, Policy, PolicyBuilder, PolicyDefinition,
+    PolicyHandle, Result,
+};
+
+/// Loads a policy from a file or a directory and reloads it when it changes on disk.
+///
+/// Changes are detected by polling the contents of the files. In a directory, only
+/// files that [`PolicyDefinition::from_dir`] reads are watched. A change is reloaded
+/// once the files stay unchanged for the debounce duration, so that a policy is not
+/// built from a partially written file. The policy is only replaced if it builds
+/// successfully; otherwise the previous policy stays active and the error is reported.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use allow_me::PolicyWatcher;
+///
+/// let watcher = PolicyWatcher::from_json_file("policy.json").unwrap();
+/// let handle = watcher.handle();
+/// let _guard = watcher.spawn(Duration::from_secs(1), |e| eprintln!("{}", e));
+/// // evaluate requests with `handle`.
+/// ```
+pub struct PolicyWatcher<R, S, N = normalizer::Default> {
+    path: PathBuf,
+    handle: Arc<PolicyHandle<R, S, N>>,
+    load: Loader<R, S, N>,
+    debounce: Duration,
+    /// The files of the active policy, or of the last failed build.
+    loaded: Fingerprint,
+    /// A change that is not loaded yet, and when it was seen.
+    pending: Option<(Fingerprint, Instant)>,
+}
+
+type Loader<R, S, N> = Box<dyn Fn(&Path) -> Result<Policy<R, S, N>> + Send>;
+
+/// Hashes of the contents of the watched files, sorted by path.
+///
+/// Contents are hashed because modification times may not change on rewrites
+/// within their granularity.
+type Fingerprint = Vec<(PathBuf, u64)>;
+
+impl PolicyWatcher<matcher::Default, DefaultSubstituter> {
+    /// Watches a json policy file, which is built with default configuration.
+    pub fn from_json_file(path: impl Into<PathBuf>) -> Result<Self> {
+        Self::new(path, |path| {
+            let json = fs::read_to_string(path).map_err(|source| Error::Io {
+                path: path.to_path_buf(),
+                source,
+            })?;
+            PolicyBuilder::from_json(json).build()
+        })
+    }
+
+    /// Watches a directory of policy files, which are built with default configuration.
+    /// See [`PolicyBuilder::from_dir`].
+    pub fn from_dir(path: impl Into<PathBuf>) -> Result<Self> {
+        Self::new(path, |path| PolicyBuilder::from_dir(path).build())
+    }
+}
+
+impl<R, S, N> PolicyWatcher<R, S, N> {
+    /// Watches the file or directory and builds the policy with `load`.
+    ///
+    /// Fails if the initial policy can not be loaded.
+    pub fn new(
+        path: impl Into<PathBuf>,
+        load: impl Fn(&Path) -> Result<Policy<R, S, N>> + Send + 'static,
+    ) -> Result<Self> {
+        let path = path.into();
+        let loaded = fingerprint(&path)?;
+        let policy = load(&path)?;
+        Ok(Self {
+            path,
+            handle: Arc::new(PolicyHandle::new(policy)),
+            load: Box::new(load),
+            debounce: Duration::from_millis(100),
+            loaded,
+            pending: None,
+        })
+    }
+
+    /// Specifies how long the files must stay unchanged before they are reloaded.
+    /// The default is 100 milliseconds.
+    #[must_use]
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Returns the handle of the active policy.
+    pub fn handle(&self) -> Arc<PolicyHandle<R, S, N>> {
+        Arc::clone(&self.handle)
+    }
+
+    /// Checks the files for changes and reloads the policy.
+    ///
+    /// Returns the generation of the new policy if it was replaced, or the error if
+    /// the changed files failed to build. A failed build is not retried until the
+    /// files change again.
+    pub fn poll(&mut self) -> Result<Option<u64>> {
+        let current = fingerprint(&self.path)?;
+        if current == self.loaded {
+            self.pending = None;
+            return Ok(None);
+        }
+        match &self.pending {
+            Some((pending, since)) if *pending == current && since.elapsed() >= self.debounce => {}
+            Some((pending, _)) if *pending == current => return Ok(None),
+            _ if self.debounce.is_zero() => {}
+            _ => {
+                self.pending = Some((current, Instant::now()));
+                return Ok(None);
+            }
+        }
+
+        self.pending = None;
+        self.loaded = current;
+        let policy = (self.load)(&self.path)?;
+        Ok(Some(self.handle.replace(policy)))
+    }
+}
+
+impl<R, S, N> PolicyWatcher<R, S, N>
+where
+    R: Send + Sync + 'static,
+    S: Send + Sync + 'static,
+    N: Send + Sync + 'static,
+{
+    /// Polls the files on a background thread until the returned guard is dropped.
+    pub fn spawn(
+        mut self,
+        interval: Duration,
+        mut on_error: impl FnMut(Error) + Send + 'static,
+    ) -> WatcherGuard {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let stop = Arc::clone(&stop);
+            move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if let Err(e) = self.poll() {
+                        on_error(e);
+                    }
+                    thread::park_timeout(interval);
+                }
+            }
+        });
+        WatcherGuard {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<R, S, N> fmt::Debug for PolicyWatcher<R, S, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyWatcher")
+            .field("path", &self.path)
+            .field("debounce", &self.debounce)
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Stops the thread of [`PolicyWatcher::spawn`] when dropped.
+#[derive(Debug)]
+pub struct WatcherGuard {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for WatcherGuard {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Returns the fingerprint of the file, or of the policy files in the directory.
+fn fingerprint(path: &Path) -> Result<Fingerprint> {
+    let io = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| Error::Io { path, source }
+    };
+    if !fs::metadata(path).map_err(io(path))?.is_dir() {
+        return Ok(vec![(path.to_path_buf(), hash(path)?)]);
+    }
+    let mut result = vec![];
+    for entry in fs::read_dir(path).map_err(io(path))? {
+        let file = entry.map_err(io(path))?.path();
+        if PolicyDefinition::parser(&file).is_some() && file.is_file() {
+            let hash = hash(&file)?;
+            result.push((file, hash));
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+fn hash(file: &Path) -> Result<u64> {
+    let contents = fs::read(file).map_err(|source| Error::Io {
+        path: file.to_path_buf(),
+        source,
+    })?;
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use assert_matches::assert_matches;
+
+    use crate::{Decision, Request};
+
+    use super::*;
+
+    fn write(path: &Path, effect: &str) {
+        let json = format!(
+            r#"{{
+                "statements": [
+                    {{
+                        "effect": "{effect}",
+                        "identities": [ "actor_a" ],
+                        "operations": [ "write" ],
+                        "resources": [ "resource_1" ]
+                    }}
+                ]
+            }}"#
+        );
+        fs::write(path, json).unwrap();
+    }
+
+    fn decision<R, S, N>(watcher: &PolicyWatcher<R, S, N>) -> Decision
+    where
+        R: crate::ResourceMatcher<Context = ()>,
+        S: crate::Substituter<Context = ()>,
+        N: crate::Normalizer,
+    {
+        let request = Request::new("actor_a", "write", "resource_1").unwrap();
+        watcher.handle().evaluate(&request).unwrap()
+    }
+
+    #[test]
+    fn reload_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+        write(&path, "allow");
+        let mut watcher = PolicyWatcher::from_json_file(&path)
+            .unwrap()
+            .with_debounce(Duration::ZERO);
+        assert_matches!(watcher.poll(), Ok(None));
+
+        write(&path, "deny");
+
+        assert_matches!(watcher.poll(), Ok(Some(1)));
+        assert_eq!(Decision::Denied, decision(&watcher));
+        assert_matches!(watcher.poll(), Ok(None));
+    }
+
+    #[test]
+    fn invalid_policy_keeps_previous_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+        write(&path, "allow");
+        let mut watcher = PolicyWatcher::from_json_file(&path)
+            .unwrap()
+            .with_debounce(Duration::ZERO);
+
+        fs::write(&path, "{ \"statements\": [").unwrap();
+
+        assert_matches!(watcher.poll(), Err(Error::Deserializing(_)));
+        assert_eq!(Decision::Allowed, decision(&watcher));
+        assert_eq!(0, watcher.handle().generation());
+        // the failed build is not retried until the file changes.
+        assert_matches!(watcher.poll(), Ok(None));
+
+        write(&path, "deny");
+        assert_matches!(watcher.poll(), Ok(Some(1)));
+    }
+
+    #[test]
+    fn debounce_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+        write(&path, "allow");
+        let mut watcher = PolicyWatcher::from_json_file(&path)
+            .unwrap()
+            .with_debounce(Duration::from_millis(50));
+
+        write(&path, "deny");
+        assert_matches!(watcher.poll(), Ok(None));
+        assert_eq!(Decision::Allowed, decision(&watcher));
+
+        thread::sleep(Duration::from_millis(60));
+        assert_matches!(watcher.poll(), Ok(Some(1)));
+        assert_eq!(Decision::Denied, decision(&watcher));
+    }
+
+    #[test]
+    fn directory_test() {
+        let dir = tempfile::tempdir().unwrap();
+        write(&dir.path().join("policy.json"), "allow");
+        let mut watcher = PolicyWatcher::new(dir.path(), |path| {
+            let json = fs::read_to_string(path.join("policy.json")).unwrap();
+            PolicyBuilder::from_json(json).build()
+        })
+        .unwrap()
+        .with_debounce(Duration::ZERO);
+
+        fs::write(dir.path().join("other.json"), "").unwrap();
+
+        assert_matches!(watcher.poll(), Ok(Some(1)));
+    }
+
+    #[test]
+    fn from_dir_test() {
+        let dir = tempfile::tempdir().unwrap();
+        write(&dir.path().join("policy.json"), "allow");
+        let mut watcher = PolicyWatcher::from_dir(dir.path())
+            .unwrap()
+            .with_debounce(Duration::ZERO);
+
+        write(&dir.path().join("00-deny.json"), "deny");
+
+        assert_matches!(watcher.poll(), Ok(Some(1)));
+        assert_eq!(Decision::Denied, decision(&watcher));
+    }
+
+    #[test]
+    fn same_size_rewrite_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+        write(&path, "deny");
+        let mut watcher = PolicyWatcher::from_json_file(&path)
+            .unwrap()
+            .with_debounce(Duration::ZERO);
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+
+        // the same size and modification time, e.g. within the mtime granularity.
+        write(&path, "alow");
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        assert_matches!(watcher.poll(), Err(Error::Deserializing(_)));
+    }
+
+    #[test]
+    fn directory_ignores_other_files_test() {
+        let dir = tempfile::tempdir().unwrap();
+        write(&dir.path().join("policy.json"), "allow");
+        let mut watcher = PolicyWatcher::from_dir(dir.path())
+            .unwrap()
+            .with_debounce(Duration::ZERO);
+
+        fs::write(dir.path().join("README.md"), "not a policy").unwrap();
+
+        assert_matches!(watcher.poll(), Ok(None));
+    }
+
+    #[test]
+    fn missing_file_test() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let error = PolicyWatcher::from_json_file(dir.path().join("policy.json")).unwrap_err();
+
+        assert_matches!(error, Error::Io { .. });
+    }
+
+    #[test]
+    fn spawn_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy.json");
+        write(&path, "allow");
+        let watcher = PolicyWatcher::from_json_file(&path)
+            .unwrap()
+            .with_debounce(Duration::ZERO);
+        let handle = watcher.handle();
+        let (sender, errors) = mpsc::channel();
+        let guard = watcher.spawn(Duration::from_millis(10), move |e| {
+            sender.send(e.to_string()).unwrap();
+        });
+
+        fs::write(&path, "{").unwrap();
+        let error = errors.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(error.contains("deserializing"), "{}", error);
+
+        write(&path, "deny");
+        let start = Instant::now();
+        while handle.generation() == 0 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(guard);
+
+        assert_eq!(1, handle.generation());
+    }
+}